@@ -0,0 +1,65 @@
+//! Compares recording the backwards light history by prepending a whole
+//! `Vec<Vec<Body>>` every step, as `Universe::new` used to, with pushing into a
+//! `BodyHistory` and reversing it once.
+//!
+//! Run with `cargo bench -p ray_tracing --bench history`.
+
+use ray_tracing::{Body, BodyHistory, StartConditions, Stepper};
+use std::time::{Duration, Instant};
+
+fn start_conditions() -> StartConditions {
+    serde_json::from_str(include_str!("../../Gravity Render.render")).unwrap()
+}
+
+fn prepend(start_conditions: &StartConditions, steps: usize) -> usize {
+    let bodies = start_conditions.initial_bodies().unwrap();
+    let mut bodies_path: Vec<Vec<Body>> = vec![bodies.clone()];
+    let mut current_bodies = bodies;
+    let mut stepper = Stepper::new(start_conditions, -start_conditions.dt);
+    for _ in 0..steps {
+        stepper.step(&mut current_bodies);
+        let mut new_vec: Vec<Vec<Body>> = vec![current_bodies.clone()];
+        new_vec.append(&mut bodies_path.clone());
+        bodies_path = new_vec.clone();
+    }
+    bodies_path.len()
+}
+
+fn flat(start_conditions: &StartConditions, steps: usize) -> usize {
+    let bodies = start_conditions.initial_bodies().unwrap();
+    let mut bodies_path = BodyHistory::with_capacity(steps + 1, bodies.len());
+    bodies_path.push(&bodies);
+    let mut current_bodies = bodies;
+    let mut stepper = Stepper::new(start_conditions, -start_conditions.dt);
+    for _ in 0..steps {
+        stepper.step(&mut current_bodies);
+        bodies_path.push(&current_bodies);
+    }
+    bodies_path.reverse();
+    bodies_path.len()
+}
+
+fn time(f: impl Fn() -> usize) -> Duration {
+    let start = Instant::now();
+    std::hint::black_box(f());
+    start.elapsed()
+}
+
+fn main() {
+    let start_conditions = start_conditions();
+    println!(
+        "{:>8} {:>14} {:>14} {:>9}",
+        "steps", "prepend", "flat", "speedup"
+    );
+    for steps in [500, 1000, 2000, 4000] {
+        let prepend = time(|| prepend(&start_conditions, steps));
+        let flat = time(|| flat(&start_conditions, steps));
+        println!(
+            "{:>8} {:>14.2?} {:>14.2?} {:>8.1}x",
+            steps,
+            prepend,
+            flat,
+            prepend.as_secs_f64() / flat.as_secs_f64()
+        );
+    }
+}
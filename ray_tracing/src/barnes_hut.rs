@@ -0,0 +1,263 @@
+use crate::{
+    physics::{pair_softening2, pull},
+    Body, Float,
+};
+use cgmath::{InnerSpace, Vector3, Zero};
+
+/// Cells deeper than this keep all of their bodies in one leaf, which stops
+/// coincident bodies from splitting the tree forever.
+const MAX_DEPTH: usize = 32;
+
+struct Node {
+    center: Vector3<Float>,
+    half_size: Float,
+    mass: Float,
+    /// Mass-weighted sum of positions, divided by `mass` once the tree is built.
+    center_of_mass: Vector3<Float>,
+    first_child: Option<usize>,
+    bodies: Vec<usize>,
+}
+
+impl Node {
+    fn new(center: Vector3<Float>, half_size: Float) -> Node {
+        Node {
+            center,
+            half_size,
+            mass: 0.0,
+            center_of_mass: Vector3::zero(),
+            first_child: None,
+            bodies: vec![],
+        }
+    }
+
+    fn octant(&self, pos: Vector3<Float>) -> usize {
+        (pos.x >= self.center.x) as usize
+            | ((pos.y >= self.center.y) as usize) << 1
+            | ((pos.z >= self.center.z) as usize) << 2
+    }
+
+    fn contains(&self, pos: Vector3<Float>) -> bool {
+        (pos.x - self.center.x).abs() <= self.half_size
+            && (pos.y - self.center.y).abs() <= self.half_size
+            && (pos.z - self.center.z).abs() <= self.half_size
+    }
+}
+
+/// Octree over the massive bodies of one snapshot, used to approximate the
+/// pull of distant groups of bodies by their centre of mass.
+pub struct Octree<'a> {
+    bodies: &'a [Body],
+    nodes: Vec<Node>,
+}
+
+impl<'a> Octree<'a> {
+    pub fn new(bodies: &'a [Body]) -> Octree<'a> {
+        let mut min = Vector3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY);
+        let mut max = -min;
+        for body in bodies.iter().filter(|body| body.mass != 0.0) {
+            min = Vector3::new(
+                min.x.min(body.pos.x),
+                min.y.min(body.pos.y),
+                min.z.min(body.pos.z),
+            );
+            max = Vector3::new(
+                max.x.max(body.pos.x),
+                max.y.max(body.pos.y),
+                max.z.max(body.pos.z),
+            );
+        }
+        let mut tree = Octree {
+            bodies,
+            nodes: vec![],
+        };
+        if min.x > max.x {
+            return tree;
+        }
+        let extent = max - min;
+        let half_size = extent.x.max(extent.y).max(extent.z) * 0.5 + Float::EPSILON;
+        tree.nodes.push(Node::new((min + max) * 0.5, half_size));
+
+        for (index, body) in bodies.iter().enumerate() {
+            if body.mass != 0.0 {
+                tree.insert(index);
+            }
+        }
+        for node in &mut tree.nodes {
+            if node.mass != 0.0 {
+                node.center_of_mass /= node.mass;
+            }
+        }
+        tree
+    }
+
+    fn insert(&mut self, index: usize) {
+        let body = &self.bodies[index];
+        let mut node = 0;
+        let mut depth = 0;
+        loop {
+            self.nodes[node].mass += body.mass;
+            self.nodes[node].center_of_mass += body.pos * body.mass;
+            match self.nodes[node].first_child {
+                Some(first_child) => {
+                    node = first_child + self.nodes[node].octant(body.pos);
+                    depth += 1;
+                }
+                None if self.nodes[node].bodies.is_empty() || depth >= MAX_DEPTH => {
+                    self.nodes[node].bodies.push(index);
+                    return;
+                }
+                None => {
+                    self.split(node);
+                    let resident = self.nodes[node].bodies.pop().unwrap();
+                    let resident_body = &self.bodies[resident];
+                    let child = self.nodes[node].first_child.unwrap()
+                        + self.nodes[node].octant(resident_body.pos);
+                    self.nodes[child].mass += resident_body.mass;
+                    self.nodes[child].center_of_mass += resident_body.pos * resident_body.mass;
+                    self.nodes[child].bodies.push(resident);
+                    node =
+                        self.nodes[node].first_child.unwrap() + self.nodes[node].octant(body.pos);
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    fn split(&mut self, node: usize) {
+        let first_child = self.nodes.len();
+        let Node {
+            center, half_size, ..
+        } = self.nodes[node];
+        let quarter = half_size * 0.5;
+        for octant in 0..8 {
+            let offset = Vector3::new(
+                if octant & 1 != 0 { quarter } else { -quarter },
+                if octant & 2 != 0 { quarter } else { -quarter },
+                if octant & 4 != 0 { quarter } else { -quarter },
+            );
+            self.nodes.push(Node::new(center + offset, quarter));
+        }
+        self.nodes[node].first_child = Some(first_child);
+    }
+
+    /// Acceleration on the body at `index`. Cells whose size over distance is
+    /// below `theta` are treated as a single point mass, softened by the mean of
+    /// the body's and the global `softening`.
+    pub fn acceleration(
+        &self,
+        index: usize,
+        gravity_strength: Float,
+        theta: Float,
+        softening: Float,
+    ) -> Vector3<Float> {
+        let mut acceleration = Vector3::zero();
+        if self.nodes.is_empty() {
+            return acceleration;
+        }
+        let body = &self.bodies[index];
+        let own_softening = body.softening.unwrap_or(softening);
+        let cell_softening2 = 0.5 * (own_softening * own_softening + softening * softening);
+
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if node.mass == 0.0 {
+                continue;
+            }
+            match node.first_child {
+                None => {
+                    for &other in &node.bodies {
+                        if other != index {
+                            let other = &self.bodies[other];
+                            acceleration += pull(
+                                other.pos - body.pos,
+                                other.mass,
+                                gravity_strength,
+                                pair_softening2(body, other, softening),
+                            );
+                        }
+                    }
+                }
+                Some(first_child) => {
+                    let distance = (node.center_of_mass - body.pos).magnitude();
+                    if !node.contains(body.pos) && node.half_size * 2.0 < theta * distance {
+                        acceleration += pull(
+                            node.center_of_mass - body.pos,
+                            node.mass,
+                            gravity_strength,
+                            cell_softening2,
+                        );
+                    } else {
+                        stack.extend(first_child..first_child + 8);
+                    }
+                }
+            }
+        }
+        acceleration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accelerations;
+    use crate::test_util::body;
+    use cgmath::vec3;
+
+    fn cluster(count: usize) -> Vec<Body> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as Float / (1u64 << 24) as Float * 2.0 - 1.0
+        };
+        (0..count)
+            .map(|i| {
+                let mass = if i % 10 == 0 { 0.0 } else { random() + 1.5 };
+                body(
+                    vec3(random(), random(), random()) * 10.0,
+                    vec3(0.0, 0.0, 0.0),
+                    mass,
+                )
+            })
+            .collect()
+    }
+
+    /// Root-mean-square of the per-body relative error against direct summation.
+    fn rms_relative_error(bodies: &[Body], theta: Float) -> Float {
+        let tree = Octree::new(bodies);
+        let direct = accelerations(bodies, 1.0, 0.0);
+        let sum: Float = direct
+            .iter()
+            .enumerate()
+            .map(|(i, direct)| {
+                ((tree.acceleration(i, 1.0, theta, 0.0) - direct).magnitude() / direct.magnitude())
+                    .powi(2)
+            })
+            .sum();
+        (sum / bodies.len() as Float).sqrt()
+    }
+
+    #[test]
+    fn zero_opening_angle_matches_direct_sum() {
+        assert!(rms_relative_error(&cluster(300), 0.0) < 1e-5);
+    }
+
+    #[test]
+    fn opening_angle_keeps_error_small() {
+        let bodies = cluster(1000);
+        let coarse = rms_relative_error(&bodies, 0.8);
+        let fine = rms_relative_error(&bodies, 0.3);
+        assert!(coarse < 5e-2, "{coarse}");
+        assert!(fine < 5e-3, "{fine}");
+        assert!(fine < coarse);
+    }
+
+    #[test]
+    fn coincident_bodies_do_not_recurse_forever() {
+        let bodies = vec![body(vec3(1.0, 2.0, 3.0), vec3(0.0, 0.0, 0.0), 1.0); 3];
+        let tree = Octree::new(&bodies);
+        assert_eq!(tree.nodes[0].mass, 3.0);
+    }
+}
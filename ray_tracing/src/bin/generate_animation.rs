@@ -1,10 +1,47 @@
 use chrono::Local;
-use ray_tracing::{trace_rays, StartConditions, Universe};
+use ray_tracing::{
+    read_universe_from_file, trace_rays, write_diagnostics_csv, write_trajectories_csv,
+    write_trajectories_jsonl, write_universe_to_file, Float, StartConditions, Universe,
+};
 use simple_video::*;
-use std::{fs::File, io::Read, path::Path, time::SystemTime};
+use std::{
+    fs::File,
+    io::{BufWriter, Read},
+    path::Path,
+    time::SystemTime,
+};
 
 fn main() {
-    let path = std::env::args().nth(1).unwrap();
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap();
+    let mut diagnostics_path = None;
+    let mut trajectories_csv_path = None;
+    let mut trajectories_jsonl_path = None;
+    let mut load_snapshot_path = None;
+    let mut save_snapshot_path = None;
+    let mut render = true;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--diagnostics-csv" => {
+                diagnostics_path = Some(args.next().expect("--diagnostics-csv needs a path"))
+            }
+            "--trajectories-csv" => {
+                trajectories_csv_path = Some(args.next().expect("--trajectories-csv needs a path"))
+            }
+            "--trajectories-jsonl" => {
+                trajectories_jsonl_path =
+                    Some(args.next().expect("--trajectories-jsonl needs a path"))
+            }
+            "--load-snapshot" => {
+                load_snapshot_path = Some(args.next().expect("--load-snapshot needs a path"))
+            }
+            "--save-snapshot" => {
+                save_snapshot_path = Some(args.next().expect("--save-snapshot needs a path"))
+            }
+            "--no-render" => render = false,
+            _ => panic!("unknown argument: {arg}"),
+        }
+    }
     let mut config = File::open(&path).unwrap();
     let mut config_string: String = "".to_string();
     let _ = config.read_to_string(&mut config_string);
@@ -20,15 +57,98 @@ fn main() {
         };
         width * height
     ];
-    let mut universe = Universe::new(&start_conditions);
+    // The snapshot replaces the physics, the render settings still come from the scene.
+    let mut universe = match &load_snapshot_path {
+        Some(snapshot_path) => match read_universe_from_file(snapshot_path) {
+            Ok(universe) => universe,
+            Err(error) => {
+                eprintln!("Could not load snapshot {snapshot_path}: {error}");
+                std::process::exit(1);
+            }
+        },
+        None => match Universe::try_new(&start_conditions) {
+            Ok(universe) => universe,
+            Err(error) => {
+                eprintln!("Simulation failed: {error}");
+                std::process::exit(1);
+            }
+        },
+    };
+    if let Some(save_snapshot_path) = save_snapshot_path {
+        write_universe_to_file(&universe, save_snapshot_path).unwrap();
+    }
 
+    let drift = universe.drift();
+    println!(
+        "Physics drift: energy {:.3e}, momentum {:.3e}, angular momentum {:.3e}",
+        drift.energy, drift.momentum, drift.angular_momentum
+    );
+    for merge in &universe.merge_events {
+        println!(
+            "Body id {} absorbed body id {} at time {:.3} ({:.2}, {:.2}, {:.2})",
+            merge.survivor, merge.absorbed, merge.time, merge.pos.x, merge.pos.y, merge.pos.z
+        );
+    }
+    if let Some(diagnostics_path) = diagnostics_path {
+        write_diagnostics_csv(
+            &universe.diagnostics(),
+            BufWriter::new(File::create(diagnostics_path).unwrap()),
+        )
+        .unwrap();
+    }
+    if let Some(trajectories_csv_path) = trajectories_csv_path {
+        write_trajectories_csv(
+            &universe,
+            BufWriter::new(File::create(trajectories_csv_path).unwrap()),
+        )
+        .unwrap();
+    }
+    if let Some(trajectories_jsonl_path) = trajectories_jsonl_path {
+        write_trajectories_jsonl(
+            &universe,
+            BufWriter::new(File::create(trajectories_jsonl_path).unwrap()),
+        )
+        .unwrap();
+    }
+    if !render {
+        return;
+    }
+
+    if let Err(error) = start_conditions.camera.at_time(&universe, 0.0) {
+        eprintln!("Invalid camera: {error}");
+        std::process::exit(1);
+    }
+    let sky = start_conditions.sky.as_ref().map(|sky| {
+        let dir = Path::new(&path).parent().unwrap_or(Path::new(""));
+        sky.load(dir).unwrap_or_else(|error| {
+            eprintln!("Could not load sky {}: {error}", sky.image.display());
+            std::process::exit(1);
+        })
+    });
     println!("Rendering Video at: {}", { Local::now().to_rfc2822() });
     let mut vid = Video::new(width as u32, height as u32, start_conditions.fps as u8);
     let start = SystemTime::now();
-    for i in 0..(universe.animation_length * vid.fps() as f32) as usize {
-        let time = i as f32 * (1.0 / vid.fps() as f32);
+    for i in 0..(universe.animation_length * vid.fps() as Float) as usize {
+        let time = i as Float * (1.0 / vid.fps() as Float);
         universe.time = time;
-        trace_rays(&mut pixels, width, height, &universe, vid.fps(), i, start);
+        let camera = start_conditions
+            .camera
+            .at_time(&universe, time)
+            .unwrap_or_else(|error| {
+                eprintln!("Invalid camera: {error}");
+                std::process::exit(1);
+            });
+        trace_rays(
+            &mut pixels,
+            width,
+            height,
+            &universe,
+            &camera,
+            sky.as_ref(),
+            vid.fps(),
+            i,
+            start,
+        );
         vid.append_frame(pixels.iter().copied().map(Into::into));
     }
     println!("\nDone at: {}", { Local::now().to_rfc2822() });
@@ -0,0 +1,235 @@
+//! Runs only the physics of a `.render` file and draws the body trajectories
+//! seen from above (+y), as a PPM image or, with `--frames`, a `.simvid`
+//! animation of the bodies moving along their trails.
+
+use ray_tracing::{read_universe_from_file, Float, StartConditions, Step, Universe};
+use simple_video::*;
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+const BACKGROUND: ColorF32 = ColorF32 {
+    r: 0.1,
+    g: 0.1,
+    b: 0.1,
+};
+
+/// Maps the xz plane onto the image, with x to the right and z downwards.
+struct Plot {
+    size: usize,
+    min_x: Float,
+    min_z: Float,
+    scale: Float,
+    pixels: Vec<ColorF32>,
+}
+
+impl Plot {
+    /// Fits every state from `first_step` on into a square image of `size` pixels.
+    fn new(universe: &Universe, first_step: usize, size: usize) -> Plot {
+        let (mut min_x, mut max_x, mut min_z, mut max_z) = (
+            Float::INFINITY,
+            Float::NEG_INFINITY,
+            Float::INFINITY,
+            Float::NEG_INFINITY,
+        );
+        for step in universe.bodies_path.steps().skip(first_step) {
+            for (pos, radius) in step.pos.iter().zip(step.radius) {
+                min_x = min_x.min(pos.x - radius);
+                max_x = max_x.max(pos.x + radius);
+                min_z = min_z.min(pos.z - radius);
+                max_z = max_z.max(pos.z + radius);
+            }
+        }
+        let extent = (max_x - min_x).max(max_z - min_z).max(1e-6) * 1.1;
+        Plot {
+            size,
+            min_x: (min_x + max_x - extent) * 0.5,
+            min_z: (min_z + max_z - extent) * 0.5,
+            scale: size as Float / extent,
+            pixels: vec![BACKGROUND; size * size],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.pixels.fill(BACKGROUND);
+    }
+
+    fn project(&self, step: &Step, index: usize) -> (Float, Float) {
+        (
+            (step.pos[index].x - self.min_x) * self.scale,
+            (step.pos[index].z - self.min_z) * self.scale,
+        )
+    }
+
+    fn set(&mut self, x: Float, y: Float, color: ColorF32) {
+        let (x, y) = (x.floor(), y.floor());
+        if x >= 0.0 && y >= 0.0 && (x as usize) < self.size && (y as usize) < self.size {
+            self.pixels[y as usize * self.size + x as usize] = color;
+        }
+    }
+
+    fn line(&mut self, from: (Float, Float), to: (Float, Float), color: ColorF32) {
+        let steps = (to.0 - from.0)
+            .abs()
+            .max((to.1 - from.1).abs())
+            .ceil()
+            .max(1.0);
+        for i in 0..=steps as usize {
+            let t = i as Float / steps;
+            self.set(
+                from.0 + (to.0 - from.0) * t,
+                from.1 + (to.1 - from.1) * t,
+                color,
+            );
+        }
+    }
+
+    fn dot(&mut self, center: (Float, Float), radius: Float, color: ColorF32) {
+        let radius = radius.max(2.0);
+        let reach = radius.ceil() as i64;
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                if ((dx * dx + dy * dy) as Float) <= radius * radius {
+                    self.set(center.0 + dx as Float, center.1 + dy as Float, color);
+                }
+            }
+        }
+    }
+
+    /// Trails of every body from `first_step` up to `last_step`, and the
+    /// bodies as dots where they are at `last_step`.
+    fn draw(&mut self, universe: &Universe, first_step: usize, last_step: usize) {
+        for i in first_step + 1..=last_step {
+            let (before, after) = (
+                universe.bodies_path.step(i - 1),
+                universe.bodies_path.step(i),
+            );
+            for b in 0..after.len() {
+                let a = if before.ids.get(b) == Some(&after.ids[b]) {
+                    Some(b)
+                } else {
+                    before.find(after.ids[b])
+                };
+                if let Some(a) = a {
+                    let color = trail_color(visible(after.color[b]));
+                    self.line(self.project(&before, a), self.project(&after, b), color);
+                }
+            }
+        }
+        let step = universe.bodies_path.step(last_step);
+        for b in 0..step.len() {
+            let radius = step.radius[b] * self.scale;
+            self.dot(self.project(&step, b), radius, visible(step.color[b]));
+        }
+    }
+}
+
+/// Bodies too dark to see against the background are drawn grey.
+fn visible(color: ColorF32) -> ColorF32 {
+    if color.r.max(color.g).max(color.b) < 0.3 {
+        ColorF32 {
+            r: 0.6,
+            g: 0.6,
+            b: 0.6,
+        }
+    } else {
+        color
+    }
+}
+
+fn trail_color(color: ColorF32) -> ColorF32 {
+    ColorF32 {
+        r: (color.r + BACKGROUND.r) * 0.5,
+        g: (color.g + BACKGROUND.g) * 0.5,
+        b: (color.b + BACKGROUND.b) * 0.5,
+    }
+}
+
+fn write_ppm(pixels: &[ColorF32], size: usize, path: &Path) -> std::io::Result<()> {
+    let mut f = BufWriter::new(File::create(path)?);
+    write!(f, "P6\n{size} {size}\n255\n")?;
+    for &pixel in pixels {
+        let ColorU8 { r, g, b } = pixel.into();
+        f.write_all(&[r, g, b])?;
+    }
+    f.flush()
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap();
+    let mut size = 800;
+    let mut frames = None;
+    let mut output = None;
+    let mut load_snapshot_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => size = args.next().expect("--size needs a number").parse().unwrap(),
+            "--frames" => {
+                frames = Some(
+                    args.next()
+                        .expect("--frames needs a number")
+                        .parse::<usize>()
+                        .unwrap(),
+                )
+            }
+            "--output" => output = Some(PathBuf::from(args.next().expect("--output needs a path"))),
+            "--load-snapshot" => {
+                load_snapshot_path = Some(args.next().expect("--load-snapshot needs a path"))
+            }
+            _ => panic!("unknown argument: {arg}"),
+        }
+    }
+    let mut config_string = String::new();
+    File::open(&path)
+        .unwrap()
+        .read_to_string(&mut config_string)
+        .unwrap();
+    let start_conditions: StartConditions = serde_json::from_str(&config_string).unwrap();
+
+    let universe = match &load_snapshot_path {
+        Some(snapshot_path) => match read_universe_from_file(snapshot_path) {
+            Ok(universe) => universe,
+            Err(error) => {
+                eprintln!("Could not load snapshot {snapshot_path}: {error}");
+                std::process::exit(1);
+            }
+        },
+        None => match Universe::try_new(&start_conditions) {
+            Ok(universe) => universe,
+            Err(error) => {
+                eprintln!("Simulation failed: {error}");
+                std::process::exit(1);
+            }
+        },
+    };
+
+    // Only the animation is plotted, not the light history before it.
+    let first_step = universe.light_iter_count();
+    let last_step = universe.bodies_path.len() - 1;
+    let mut plot = Plot::new(&universe, first_step, size);
+    match frames {
+        None => {
+            plot.draw(&universe, first_step, last_step);
+            let output = output.unwrap_or_else(|| Path::new(&path).with_file_name("preview.ppm"));
+            write_ppm(&plot.pixels, size, &output).unwrap();
+            println!("Wrote {}", output.display());
+        }
+        Some(frames) => {
+            let mut video = Video::new(size as u32, size as u32, start_conditions.fps as u8);
+            for frame in 0..frames {
+                let step =
+                    first_step + (last_step - first_step) * frame / frames.saturating_sub(1).max(1);
+                plot.clear();
+                plot.draw(&universe, first_step, step);
+                video.append_frame(plot.pixels.iter().copied().map(Into::into));
+            }
+            let output =
+                output.unwrap_or_else(|| Path::new(&path).with_file_name("preview.simvid"));
+            write_video_to_file(&video, &output).unwrap();
+            println!("Wrote {}", output.display());
+        }
+    }
+}
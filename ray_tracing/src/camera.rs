@@ -0,0 +1,634 @@
+use crate::{physics::hermite, BodyRef, Float, Lerp, SimulationError, Universe};
+use cgmath::{vec3, InnerSpace, Quaternion, Rotation, Vector3, Zero};
+
+/// Pose of the camera at `time` on the render timeline.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CameraKeyframe {
+    pub time: Float,
+    pub position: Vector3<Float>,
+    /// Left out to keep the orientation of the camera itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<Quaternion<Float>>,
+    /// Left out to keep the field of view of the camera itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vertical_fov: Option<Float>,
+}
+
+/// Face of a cube map, named by the direction it shows relative to the camera.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CubeFace {
+    Front,
+    Back,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// How pixels are mapped to the directions of their primary rays.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Projection {
+    /// Pinhole camera with `vertical_fov` across the height of the image.
+    #[default]
+    Perspective,
+    /// The whole sky, with longitude across the width of the image and
+    /// latitude across its height, so the image should be twice as wide as
+    /// it is high.
+    Equirectangular,
+    /// Equidistant fisheye, whose angle from the view direction grows evenly
+    /// with the distance from the middle of the image to `vertical_fov / 2`
+    /// at the top and bottom edges. Pixels more than 180° out stay black.
+    Fisheye,
+    /// Parallel rays from a plane `height` high around the camera.
+    Orthographic { height: Float },
+    /// One square 90° face of a cube map around the camera.
+    CubeFace(CubeFace),
+}
+
+/// Where the two eyes of a stereo camera go in the frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum StereoLayout {
+    /// Left eye in the left half.
+    #[default]
+    SideBySide,
+    /// Left eye in the top half.
+    TopBottom,
+}
+
+/// Renders a view for each eye into the same frame, so both see the bodies at
+/// the same time.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Stereo {
+    #[serde(default)]
+    pub layout: StereoLayout,
+    /// Distance between the eyes, which sit on either side of `position`.
+    pub interocular_distance: Float,
+}
+
+/// How the camera moves between its keyframes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CameraInterpolation {
+    /// Straight lines between the positions, and orientations blended and
+    /// renormalized.
+    #[default]
+    Linear,
+    /// Catmull-Rom spline through the positions, and spherical interpolation
+    /// between orientations.
+    Smooth,
+}
+
+/// Where the primary rays start and where they go. The default is the camera
+/// every scene used before it could be configured: at the origin, looking
+/// along +z with +x to the right of the image and +y towards its bottom.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Camera {
+    #[serde(default = "Vector3::zero")]
+    pub position: Vector3<Float>,
+    /// Point in the middle of the image, which takes precedence over
+    /// `orientation`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at: Option<Vector3<Float>>,
+    /// Rotation from camera space into the world. In camera space +x is to
+    /// the right of the image, +y towards its bottom and +z forward.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<Quaternion<Float>>,
+    /// Direction towards the top of the image when using `look_at`, -y by
+    /// default like the default view, so that looking straight ahead along +z
+    /// changes nothing. Looking along it tilts the image from the +z view.
+    #[serde(default = "Camera::default_up")]
+    pub up: Vector3<Float>,
+    /// Vertical field of view in degrees.
+    #[serde(default = "Camera::default_vertical_fov")]
+    pub vertical_fov: Float,
+    #[serde(default)]
+    pub projection: Projection,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stereo: Option<Stereo>,
+    /// Poses in time order that replace the ones above while rendering. The
+    /// camera holds still before the first and after the last one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keyframes: Vec<CameraKeyframe>,
+    #[serde(default)]
+    pub interpolation: CameraInterpolation,
+    /// Body that carries the camera along, making `position` relative to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub follow: Option<BodyRef>,
+    /// Body in the middle of the image, which takes precedence over every
+    /// other orientation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at_body: Option<BodyRef>,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera {
+            position: Vector3::zero(),
+            look_at: None,
+            orientation: None,
+            up: Camera::default_up(),
+            vertical_fov: Camera::default_vertical_fov(),
+            projection: Projection::Perspective,
+            stereo: None,
+            keyframes: vec![],
+            interpolation: CameraInterpolation::Linear,
+            follow: None,
+            look_at_body: None,
+        }
+    }
+}
+
+impl Camera {
+    fn default_up() -> Vector3<Float> {
+        vec3(0.0, -1.0, 0.0)
+    }
+
+    fn default_vertical_fov() -> Float {
+        90.0
+    }
+
+    /// The still camera at `time`, with its keyframes interpolated and the
+    /// bodies it follows or looks at where they are in `universe`.
+    pub fn at_time(&self, universe: &Universe, time: Float) -> Result<Camera, SimulationError> {
+        let mut camera = Camera {
+            keyframes: vec![],
+            follow: None,
+            look_at_body: None,
+            ..self.clone()
+        };
+        if let Some(pose) = self.pose_at(time) {
+            camera.position = pose.position;
+            if let Some(orientation) = pose.orientation {
+                camera.look_at = None;
+                camera.orientation = Some(orientation);
+            }
+            if let Some(vertical_fov) = pose.vertical_fov {
+                camera.vertical_fov = vertical_fov;
+            }
+        }
+        if let Some(follow) = &self.follow {
+            camera.position += target_pos(follow, universe, time)?;
+        }
+        if let Some(look_at_body) = &self.look_at_body {
+            camera.look_at = Some(target_pos(look_at_body, universe, time)?);
+        }
+        if camera.look_at == Some(camera.position) {
+            return Err(SimulationError::CameraAtTarget { time });
+        }
+        Ok(camera)
+    }
+
+    fn pose_at(&self, time: Float) -> Option<CameraKeyframe> {
+        let keyframes = &self.keyframes;
+        let next = keyframes.iter().position(|keyframe| keyframe.time > time);
+        let next = match next {
+            None => return keyframes.last().cloned(),
+            Some(0) => return Some(keyframes[0].clone()),
+            Some(next) => next,
+        };
+        let (a, b) = (&keyframes[next - 1], &keyframes[next]);
+        let h = b.time - a.time;
+        let s = (time - a.time) / h;
+        // Catmull-Rom tangents, one-sided at the ends.
+        let tangent = |i: usize| {
+            let (before, after) = (
+                &keyframes[i.saturating_sub(1)],
+                &keyframes[(i + 1).min(keyframes.len() - 1)],
+            );
+            (after.position - before.position) / (after.time - before.time)
+        };
+        let nearest = if s < 0.5 { a } else { b };
+        Some(CameraKeyframe {
+            time,
+            position: match self.interpolation {
+                CameraInterpolation::Linear => Lerp::lerp(a.position, b.position, s),
+                CameraInterpolation::Smooth => hermite(
+                    a.position,
+                    tangent(next - 1),
+                    b.position,
+                    tangent(next),
+                    h,
+                    s,
+                ),
+            },
+            orientation: match (a.orientation, b.orientation) {
+                (Some(from), Some(to)) => Some(match self.interpolation {
+                    CameraInterpolation::Linear => from.normalize().nlerp(to.normalize(), s),
+                    CameraInterpolation::Smooth => from.normalize().slerp(to.normalize(), s),
+                }),
+                _ => nearest.orientation,
+            },
+            vertical_fov: match (a.vertical_fov, b.vertical_fov) {
+                (Some(from), Some(to)) => Some(Float::lerp(from, to, s)),
+                _ => nearest.vertical_fov,
+            },
+        })
+    }
+
+    /// The right, down and forward directions of the image in the world.
+    pub fn basis(&self) -> [Vector3<Float>; 3] {
+        if let Some(look_at) = self.look_at {
+            let forward = (look_at - self.position).normalize();
+            let mut right = forward.cross(self.up);
+            if right.magnitude2() < 1e-12 {
+                // Looking along `up`, the image is turned as if the camera
+                // had tilted there from looking along +z, or along +x when
+                // `up` is along z itself.
+                let level = if self.up.z.abs() < 0.9 {
+                    Vector3::unit_z()
+                } else {
+                    Vector3::unit_x()
+                };
+                right = forward.cross(level * -forward.dot(self.up).signum());
+            }
+            let right = right.normalize();
+            [right, forward.cross(right), forward]
+        } else if let Some(orientation) = self.orientation {
+            let orientation = orientation.normalize();
+            [
+                orientation.rotate_vector(Vector3::unit_x()),
+                orientation.rotate_vector(Vector3::unit_y()),
+                orientation.rotate_vector(Vector3::unit_z()),
+            ]
+        } else {
+            [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()]
+        }
+    }
+
+    /// Start and direction, not normalized, of the primary ray through
+    /// `(x, y)`, both from 0 to 1 across the image. `None` for pixels that
+    /// the projection leaves empty.
+    pub fn primary_ray(
+        &self,
+        x: Float,
+        y: Float,
+        aspect: Float,
+    ) -> Option<(Vector3<Float>, Vector3<Float>)> {
+        let Some(stereo) = self.stereo else {
+            return self.eye_ray(x, y, aspect);
+        };
+        let (eye, x, y, aspect) = match stereo.layout {
+            StereoLayout::SideBySide if x < 0.5 => (-1.0, x * 2.0, y, aspect * 0.5),
+            StereoLayout::SideBySide => (1.0, x * 2.0 - 1.0, y, aspect * 0.5),
+            StereoLayout::TopBottom if y < 0.5 => (-1.0, x, y * 2.0, aspect * 2.0),
+            StereoLayout::TopBottom => (1.0, x, y * 2.0 - 1.0, aspect * 2.0),
+        };
+        let (start, direction) = self.eye_ray(x, y, aspect)?;
+        let [right, down, _] = self.basis();
+        // Projections that see all around put the eyes on a circle, at right
+        // angles to each ray, which fades to mono straight up and down.
+        let sideways = match self.projection {
+            Projection::Perspective | Projection::Orthographic { .. } => right,
+            Projection::Equirectangular | Projection::Fisheye | Projection::CubeFace(_) => {
+                down.cross(direction) / direction.magnitude()
+            }
+        };
+        Some((
+            start + sideways * (eye * stereo.interocular_distance * 0.5),
+            direction,
+        ))
+    }
+
+    /// `primary_ray` for a single eye at `position`.
+    fn eye_ray(
+        &self,
+        x: Float,
+        y: Float,
+        aspect: Float,
+    ) -> Option<(Vector3<Float>, Vector3<Float>)> {
+        let [right, down, forward] = self.basis();
+        let (u, v) = (x * 2.0 - 1.0, y * 2.0 - 1.0);
+        let direction = match self.projection {
+            Projection::Perspective => {
+                let scale = (self.vertical_fov * 0.5).to_radians().tan();
+                right * (u * aspect * scale) + down * (v * scale) + forward
+            }
+            Projection::Equirectangular => {
+                let longitude = u * std::f64::consts::PI as Float;
+                let latitude = v * std::f64::consts::FRAC_PI_2 as Float;
+                (forward * longitude.cos() + right * longitude.sin()) * latitude.cos()
+                    + down * latitude.sin()
+            }
+            Projection::Fisheye => {
+                let (u, v) = (u * aspect, v);
+                let radius = (u * u + v * v).sqrt();
+                let angle = radius * (self.vertical_fov * 0.5).to_radians();
+                if angle > std::f64::consts::PI as Float {
+                    return None;
+                }
+                if radius == 0.0 {
+                    forward
+                } else {
+                    forward * angle.cos() + (right * u + down * v) * (angle.sin() / radius)
+                }
+            }
+            Projection::Orthographic { height } => {
+                let offset = right * (u * aspect) + down * v;
+                return Some((self.position + offset * (height * 0.5), forward));
+            }
+            Projection::CubeFace(face) => {
+                let [right, down, forward] = match face {
+                    CubeFace::Front => [right, down, forward],
+                    CubeFace::Back => [-right, down, -forward],
+                    CubeFace::Left => [forward, down, -right],
+                    CubeFace::Right => [-forward, down, right],
+                    CubeFace::Up => [right, forward, -down],
+                    CubeFace::Down => [right, -forward, down],
+                };
+                right * u + down * v + forward
+            }
+        };
+        Some((self.position, direction))
+    }
+}
+
+/// Position of `target` at `time`, or where it last was if it has merged
+/// into another body.
+fn target_pos(
+    target: &BodyRef,
+    universe: &Universe,
+    time: Float,
+) -> Result<Vector3<Float>, SimulationError> {
+    let unknown = || SimulationError::UnknownCameraTarget(target.to_string());
+    let id = match target {
+        BodyRef::Id(id) => *id,
+        BodyRef::Name(name) => universe.find_body(name).ok_or_else(unknown)?,
+    };
+    if let Some(body) = universe.body_at_time(id, time) {
+        return Ok(body.pos);
+    }
+    let trajectory = universe.trajectory(id);
+    let last = trajectory.iter().rev().find(|(at, _)| *at <= time);
+    let (_, body) = last.or(trajectory.first()).ok_or_else(unknown)?;
+    Ok(body.pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+    use crate::Integrator;
+    use cgmath::{Deg, One, Rotation3};
+
+    #[test]
+    fn default_camera_matches_the_old_rays() {
+        let camera: Camera = serde_json::from_str("{}").unwrap();
+        assert_eq!(camera, Camera::default());
+        for (x, y) in [(0.0, 0.0), (0.3, 0.9), (1.0, 0.5)] {
+            let old = vec3((x * 2.0 - 1.0) * 1.5, y * 2.0 - 1.0, 1.0);
+            let (start, new) = camera.primary_ray(x, y, 1.5).unwrap();
+            assert_eq!(start, vec3(0.0, 0.0, 0.0));
+            assert!((new - old).magnitude() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn look_at_and_orientation_agree() {
+        // Looking straight ahead is the default view.
+        let ahead = Camera {
+            look_at: Some(vec3(0.0, 0.0, 1.0)),
+            ..Camera::default()
+        };
+        assert_eq!(ahead.basis(), Camera::default().basis());
+
+        let looking = Camera {
+            position: vec3(0.0, 0.0, -10.0),
+            look_at: Some(vec3(10.0, 0.0, -10.0)),
+            up: vec3(0.0, 1.0, 0.0),
+            vertical_fov: 60.0,
+            ..Camera::default()
+        };
+        let [right, down, forward] = looking.basis();
+        assert!((forward - vec3(1.0, 0.0, 0.0)).magnitude() < 1e-6);
+        assert!((down - vec3(0.0, -1.0, 0.0)).magnitude() < 1e-6);
+        assert!((right - vec3(0.0, 0.0, 1.0)).magnitude() < 1e-6);
+        // The top edge of the image is half the field of view above the horizon.
+        let top = looking.primary_ray(0.5, 0.0, 2.0).unwrap().1.normalize();
+        assert!((top.y - 0.5).abs() < 1e-6);
+
+        // Turning the default camera upright and then a quarter
+        // turn about +y gives the same view.
+        let rotated = Camera {
+            look_at: None,
+            orientation: Some(
+                Quaternion::from_angle_y(Deg(90.0)) * Quaternion::from_angle_z(Deg(180.0)),
+            ),
+            ..looking.clone()
+        };
+        for (x, y) in [(0.1, 0.2), (0.7, 0.4)] {
+            let a = looking.primary_ray(x, y, 2.0).unwrap().1;
+            let b = rotated.primary_ray(x, y, 2.0).unwrap().1;
+            assert!((a - b).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn keyframes_and_targets_move_the_camera() {
+        let turned = Quaternion::from_angle_y(Deg(90.0));
+        let keyframe = |time, position, orientation| CameraKeyframe {
+            time,
+            position,
+            orientation,
+            vertical_fov: None,
+        };
+        let mut camera = Camera {
+            keyframes: vec![
+                keyframe(0.0, vec3(0.0, 0.0, 0.0), Some(Quaternion::one())),
+                keyframe(2.0, vec3(2.0, 0.0, 0.0), Some(turned)),
+                keyframe(4.0, vec3(2.0, 0.0, 2.0), None),
+            ],
+            ..Camera::default()
+        };
+        let mut sun = body(vec3(5.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0);
+        sun.name = Some("sun".to_string());
+        sun.motion = crate::Motion::Fixed;
+        let universe = Universe::new(&start_conditions(vec![sun], Integrator::Leapfrog));
+
+        let at = |camera: &Camera, time| camera.at_time(&universe, time).unwrap();
+        assert_eq!(at(&camera, -1.0).position, vec3(0.0, 0.0, 0.0));
+        assert_eq!(at(&camera, 5.0).position, vec3(2.0, 0.0, 2.0));
+        assert!((at(&camera, 1.0).position - vec3(1.0, 0.0, 0.0)).magnitude() < 1e-6);
+        assert!((at(&camera, 3.0).position - vec3(2.0, 0.0, 1.0)).magnitude() < 1e-6);
+
+        camera.interpolation = CameraInterpolation::Smooth;
+        assert!((at(&camera, 2.0).position - vec3(2.0, 0.0, 0.0)).magnitude() < 1e-6);
+        // The spline swings out ahead of the turn towards the next keyframe.
+        assert!(at(&camera, 1.5).position.z < 0.0);
+        let [_, _, forward] = at(&camera, 1.0).basis();
+        let diagonal = vec3(1.0, 0.0, 1.0).normalize();
+        assert!((forward - diagonal).magnitude() < 1e-5);
+        // Next to a keyframe without an orientation the nearest one is held.
+        assert_eq!(at(&camera, 2.5).orientation, Some(turned));
+
+        let camera = Camera {
+            position: vec3(0.0, 0.0, -3.0),
+            follow: Some(BodyRef::Name("sun".to_string())),
+            look_at_body: Some(BodyRef::Id(0)),
+            ..Camera::default()
+        };
+        let posed = at(&camera, 0.5);
+        assert_eq!(posed.position, vec3(5.0, 0.0, -3.0));
+        assert_eq!(posed.look_at, Some(vec3(5.0, 0.0, 0.0)));
+        let lost = Camera {
+            follow: Some(BodyRef::Name("moon".to_string())),
+            ..camera
+        };
+        assert!(lost.at_time(&universe, 0.0).is_err());
+    }
+
+    #[test]
+    fn looking_along_up_tilts_the_default_view() {
+        let looking = |look_at, up| Camera {
+            look_at: Some(look_at),
+            up,
+            ..Camera::default()
+        };
+        let close = |a: [Vector3<Float>; 3], b: [Vector3<Float>; 3]| {
+            a.iter().zip(&b).all(|(a, b)| (a - b).magnitude() < 1e-6)
+        };
+        let (x, y, z) = (Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z());
+        // Straight down and straight up, as if tilted from looking along +z.
+        let down = looking(vec3(0.0, 5.0, 0.0), -y);
+        assert!(close(down.basis(), [x, -z, y]), "{:?}", down.basis());
+        assert!(close(looking(-y, -y).basis(), [x, z, -y]));
+        let (_, middle) = down.primary_ray(0.5, 0.5, 1.5).unwrap();
+        assert!((middle - y).magnitude() < 1e-6);
+        for (x, y) in [(0.0, 0.0), (0.3, 0.9), (1.0, 0.5)] {
+            let (_, direction) = down.primary_ray(x, y, 1.5).unwrap();
+            assert!(direction.magnitude().is_finite());
+        }
+
+        let [right, down, forward] = looking(z * 3.0, z).basis();
+        assert!((right.magnitude() - 1.0).abs() < 1e-6);
+        assert!(right.dot(forward).abs() < 1e-6 && down.dot(forward).abs() < 1e-6);
+    }
+
+    #[test]
+    fn camera_on_its_target_is_an_error() {
+        let mut sun = body(vec3(5.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0);
+        sun.motion = crate::Motion::Fixed;
+        let universe = Universe::new(&start_conditions(vec![sun], Integrator::Leapfrog));
+        let camera = Camera {
+            follow: Some(BodyRef::Id(0)),
+            look_at_body: Some(BodyRef::Id(0)),
+            ..Camera::default()
+        };
+        assert_eq!(
+            camera.at_time(&universe, 0.5),
+            Err(SimulationError::CameraAtTarget { time: 0.5 })
+        );
+        let still = Camera {
+            look_at: Some(Vector3::zero()),
+            ..Camera::default()
+        };
+        assert!(still.at_time(&universe, 0.0).is_err());
+    }
+
+    #[test]
+    fn projections_cover_their_views() {
+        let camera = |projection| Camera {
+            position: vec3(1.0, 2.0, 3.0),
+            projection,
+            ..Camera::default()
+        };
+        let direction = |camera: &Camera, x, y| {
+            let (start, direction) = camera.primary_ray(x, y, 2.0).unwrap();
+            assert_eq!(start, vec3(1.0, 2.0, 3.0));
+            direction.normalize()
+        };
+        let close = |a: Vector3<Float>, b: Vector3<Float>| (a - b).magnitude() < 1e-5;
+
+        let panorama = camera(Projection::Equirectangular);
+        assert!(close(direction(&panorama, 0.5, 0.5), Vector3::unit_z()));
+        assert!(close(direction(&panorama, 0.75, 0.5), Vector3::unit_x()));
+        assert!(close(direction(&panorama, 0.0, 0.5), -Vector3::unit_z()));
+        assert!(close(direction(&panorama, 0.3, 0.0), -Vector3::unit_y()));
+
+        let mut fisheye = camera(Projection::Fisheye);
+        fisheye.vertical_fov = 180.0;
+        assert!(close(direction(&fisheye, 0.5, 0.5), Vector3::unit_z()));
+        assert!(close(direction(&fisheye, 0.5, 1.0), Vector3::unit_y()));
+        assert!(close(direction(&fisheye, 0.0, 0.5), -Vector3::unit_z()));
+        assert_eq!(fisheye.primary_ray(0.0, 0.0, 2.0), None);
+
+        let orthographic = camera(Projection::Orthographic { height: 4.0 });
+        let (start, towards) = orthographic.primary_ray(1.0, 0.25, 2.0).unwrap();
+        assert_eq!(start, vec3(5.0, 1.0, 3.0));
+        assert_eq!(towards, Vector3::unit_z());
+
+        let face = |face| camera(Projection::CubeFace(face));
+        let faces = [
+            (CubeFace::Front, Vector3::unit_z()),
+            (CubeFace::Back, -Vector3::unit_z()),
+            (CubeFace::Left, -Vector3::unit_x()),
+            (CubeFace::Right, Vector3::unit_x()),
+            (CubeFace::Up, -Vector3::unit_y()),
+            (CubeFace::Down, Vector3::unit_y()),
+        ];
+        for (cube_face, middle) in faces {
+            assert!(close(direction(&face(cube_face), 0.5, 0.5), middle));
+        }
+        // Neighbouring faces meet at their edges.
+        let (front, right, up) = (
+            face(CubeFace::Front),
+            face(CubeFace::Right),
+            face(CubeFace::Up),
+        );
+        assert!(close(
+            direction(&front, 1.0, 0.3),
+            direction(&right, 0.0, 0.3)
+        ));
+        assert!(close(direction(&front, 0.6, 0.0), direction(&up, 0.6, 1.0)));
+    }
+
+    #[test]
+    fn stereo_eyes_share_the_frame() {
+        let mono = Camera::default();
+        let stereo = Camera {
+            stereo: Some(Stereo {
+                layout: StereoLayout::SideBySide,
+                interocular_distance: 0.2,
+            }),
+            ..mono.clone()
+        };
+        let left = Camera {
+            position: vec3(-0.1, 0.0, 0.0),
+            ..mono.clone()
+        };
+        let right = Camera {
+            position: vec3(0.1, 0.0, 0.0),
+            ..mono.clone()
+        };
+        let close = |a: Option<(Vector3<Float>, Vector3<Float>)>,
+                     b: Option<(Vector3<Float>, Vector3<Float>)>| {
+            let ((a_start, a_direction), (b_start, b_direction)) = (a.unwrap(), b.unwrap());
+            (a_start - b_start).magnitude() < 1e-6
+                && (a_direction.normalize() - b_direction.normalize()).magnitude() < 1e-6
+        };
+        assert!(close(
+            stereo.primary_ray(0.1, 0.3, 2.0),
+            left.primary_ray(0.2, 0.3, 1.0)
+        ));
+        assert!(close(
+            stereo.primary_ray(0.6, 0.3, 2.0),
+            right.primary_ray(0.2, 0.3, 1.0)
+        ));
+
+        let over_under = Camera {
+            projection: Projection::Equirectangular,
+            stereo: Some(Stereo {
+                layout: StereoLayout::TopBottom,
+                interocular_distance: 0.2,
+            }),
+            ..Camera::default()
+        };
+        // Looking to the right, the left eye is in front of the camera.
+        let (start, direction) = over_under.primary_ray(0.75, 0.25, 1.0).unwrap();
+        assert!((start - vec3(0.0, 0.0, 0.1)).magnitude() < 1e-6);
+        assert!((direction.normalize() - Vector3::unit_x()).magnitude() < 1e-6);
+        let (start, _) = over_under.primary_ray(0.75, 0.75, 1.0).unwrap();
+        assert!((start - vec3(0.0, 0.0, -0.1)).magnitude() < 1e-6);
+        // Straight up both eyes see the same.
+        let (start, _) = over_under.primary_ray(0.2, 0.0, 1.0).unwrap();
+        assert!(start.magnitude() < 1e-6);
+    }
+}
@@ -0,0 +1,221 @@
+use crate::{to_f32, Body, Float};
+use cgmath::{InnerSpace, MetricSpace, Vector3};
+use simple_video::ColorF32;
+
+/// What happens when two bodies overlap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CollisionMode {
+    /// Bodies pass through each other.
+    #[default]
+    Ignore,
+    /// Perfectly inelastic merge into one body that conserves mass and momentum.
+    Merge,
+    /// Perfectly elastic bounce off each other's surfaces.
+    Bounce,
+}
+
+/// Record of the body with id `absorbed` merging into `survivor`, which keeps
+/// its id and name.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MergeEvent {
+    pub time: Float,
+    pub survivor: u32,
+    pub absorbed: u32,
+    pub pos: Vector3<Float>,
+}
+
+fn overlapping(a: &Body, b: &Body) -> bool {
+    a.pos.distance2(b.pos) < (a.radius + b.radius) * (a.radius + b.radius)
+}
+
+/// Weights of `a` and `b` in a mass-weighted average, falling back to equal
+/// weights when both are massless.
+fn mass_weights(a: &Body, b: &Body) -> (Float, Float) {
+    let total = a.mass + b.mass;
+    if total == 0.0 {
+        (0.5, 0.5)
+    } else {
+        (a.mass / total, b.mass / total)
+    }
+}
+
+/// `a` and `b` as one body, which keeps the identity of the heavier one.
+fn merged(a: &Body, b: &Body) -> Body {
+    let (wa, wb) = mass_weights(a, b);
+    let heavier = if b.mass > a.mass { b } else { a };
+    let (color_a, color_b) = (to_f32(wa), to_f32(wb));
+    Body {
+        pos: a.pos * wa + b.pos * wb,
+        vel: a.vel * wa + b.vel * wb,
+        radius: (a.radius.powi(3) + b.radius.powi(3)).cbrt(),
+        color: ColorF32 {
+            r: a.color.r * color_a + b.color.r * color_b,
+            g: a.color.g * color_a + b.color.g * color_b,
+            b: a.color.b * color_a + b.color.b * color_b,
+        },
+        mass: a.mass + b.mass,
+        ..heavier.clone()
+    }
+}
+
+/// Elastic bounce along the line between the centres, which also pushes the
+/// bodies apart until they only touch.
+fn bounce(bodies: &mut [Body], a: usize, b: usize) {
+    let (wa, wb) = mass_weights(&bodies[a], &bodies[b]);
+    let offset = bodies[b].pos - bodies[a].pos;
+    let distance = offset.magnitude();
+    let normal = if distance == 0.0 {
+        Vector3::unit_x()
+    } else {
+        offset / distance
+    };
+    let overlap = bodies[a].radius + bodies[b].radius - distance;
+    bodies[a].pos -= normal * (overlap * wb);
+    bodies[b].pos += normal * (overlap * wa);
+
+    let approach = (bodies[a].vel - bodies[b].vel).dot(normal);
+    if approach > 0.0 {
+        bodies[a].vel -= normal * (2.0 * wb * approach);
+        bodies[b].vel += normal * (2.0 * wa * approach);
+    }
+}
+
+/// Resolves every overlap in `bodies` according to `mode`, recording merges in
+/// `merges`. Returns whether any body was changed.
+pub(crate) fn resolve_collisions(
+    bodies: &mut Vec<Body>,
+    mode: CollisionMode,
+    time: Float,
+    merges: &mut Vec<MergeEvent>,
+) -> bool {
+    let mut changed = false;
+    match mode {
+        CollisionMode::Ignore => {}
+        CollisionMode::Merge => {
+            let mut a = 0;
+            while a < bodies.len() {
+                let mut b = a + 1;
+                while b < bodies.len() {
+                    if overlapping(&bodies[a], &bodies[b]) {
+                        let body = merged(&bodies[a], &bodies[b]);
+                        let absorbed = if body.id == bodies[a].id {
+                            bodies[b].id
+                        } else {
+                            bodies[a].id
+                        };
+                        merges.push(MergeEvent {
+                            time,
+                            survivor: body.id,
+                            absorbed,
+                            pos: body.pos,
+                        });
+                        bodies[a] = body;
+                        bodies.remove(b);
+                        changed = true;
+                        // The grown body may now overlap ones already checked.
+                        b = a + 1;
+                    } else {
+                        b += 1;
+                    }
+                }
+                a += 1;
+            }
+        }
+        CollisionMode::Bounce => {
+            for a in 0..bodies.len() {
+                for b in a + 1..bodies.len() {
+                    if overlapping(&bodies[a], &bodies[b]) {
+                        bounce(bodies, a, b);
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+    use crate::{Integrator, Universe};
+    use cgmath::vec3;
+
+    #[test]
+    fn merge_conserves_mass_and_momentum() {
+        let mut a = body(vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 1.0);
+        a.radius = 1.0;
+        a.id = 7;
+        let mut b = body(vec3(1.5, 0.0, 0.0), vec3(-1.0, 2.0, 0.0), 3.0);
+        b.radius = 1.0;
+        b.id = 3;
+        b.color = ColorF32 {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+        };
+        let mut bodies = vec![a, b];
+        let mut merges = vec![];
+        assert!(resolve_collisions(
+            &mut bodies,
+            CollisionMode::Merge,
+            1.0,
+            &mut merges
+        ));
+
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0].mass, 4.0);
+        assert_eq!(bodies[0].vel, vec3(-0.5, 1.5, 0.0));
+        assert_eq!(bodies[0].pos, vec3(1.125, 0.0, 0.0));
+        assert!((bodies[0].radius - (2.0 as Float).cbrt()).abs() < 1e-6);
+        assert_eq!(bodies[0].color.r, 0.25);
+        assert_eq!(bodies[0].id, 3);
+        assert_eq!(merges.len(), 1);
+        assert_eq!((merges[0].survivor, merges[0].absorbed), (3, 7));
+    }
+
+    #[test]
+    fn equal_masses_swap_velocities_on_bounce() {
+        let mut bodies = vec![
+            body(vec3(-0.05, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 1.0),
+            body(vec3(0.05, 0.0, 0.0), vec3(-1.0, 0.0, 0.0), 1.0),
+        ];
+        let mut merges = vec![];
+        assert!(resolve_collisions(
+            &mut bodies,
+            CollisionMode::Bounce,
+            0.0,
+            &mut merges
+        ));
+        assert_eq!(bodies[0].vel, vec3(-1.0, 0.0, 0.0));
+        assert_eq!(bodies[1].vel, vec3(1.0, 0.0, 0.0));
+        assert!(!overlapping(&bodies[0], &bodies[1]));
+        assert!(merges.is_empty());
+    }
+
+    #[test]
+    fn universe_history_shrinks_after_merge() {
+        let mut start_conditions = start_conditions(
+            vec![
+                body(vec3(-1.0, 0.0, 0.0), vec3(2.0, 0.0, 0.0), 1.0),
+                body(vec3(1.0, 0.0, 0.0), vec3(-2.0, 0.0, 0.0), 1.0),
+                body(vec3(0.0, 5.0, 0.0), vec3(0.0, 0.0, 0.0), 0.0),
+            ],
+            Integrator::Leapfrog,
+        );
+        start_conditions.softening = 0.05;
+        start_conditions.collisions = CollisionMode::Merge;
+        let universe = Universe::new(&start_conditions);
+
+        assert_eq!(universe.bodies_path.first().unwrap().len(), 3);
+        assert_eq!(universe.bodies_path.last().unwrap().len(), 2);
+        assert_eq!(universe.merge_events.len(), 1);
+        assert!(universe.merge_events[0].time > 0.0);
+        assert_eq!(universe.merge_events[0].survivor, 0);
+        assert_eq!(universe.merge_events[0].absorbed, 1);
+        let last_seen = universe.trajectory(1).last().unwrap().0;
+        assert!((last_seen - (universe.merge_events[0].time - universe.dt)).abs() < 1e-4);
+        assert_eq!(universe.find_body("missing"), None);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{physics::pair_softening2, Body, Float, Universe};
+use cgmath::{InnerSpace, MetricSpace, Vector3, Zero};
+use std::io::Write;
+
+/// Conserved quantities of the bodies at one entry of `bodies_path`.
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct Diagnostics {
+    pub time: Float,
+    pub kinetic_energy: Float,
+    pub potential_energy: Float,
+    pub momentum: Vector3<Float>,
+    pub angular_momentum: Vector3<Float>,
+}
+
+impl Diagnostics {
+    /// The potential energy uses the same Plummer softening as the simulation.
+    pub fn measure(
+        time: Float,
+        bodies: &[Body],
+        gravity_strength: Float,
+        softening: Float,
+    ) -> Diagnostics {
+        let mut diagnostics = Diagnostics {
+            time,
+            kinetic_energy: 0.0,
+            potential_energy: 0.0,
+            momentum: Vector3::zero(),
+            angular_momentum: Vector3::zero(),
+        };
+        for (a, body) in bodies.iter().enumerate() {
+            diagnostics.kinetic_energy += 0.5 * body.mass * body.vel.magnitude2();
+            diagnostics.momentum += body.vel * body.mass;
+            diagnostics.angular_momentum += body.pos.cross(body.vel * body.mass);
+            for other in &bodies[a + 1..] {
+                if body.mass != 0.0 && other.mass != 0.0 {
+                    let distance2 =
+                        body.pos.distance2(other.pos) + pair_softening2(body, other, softening);
+                    diagnostics.potential_energy -=
+                        gravity_strength * body.mass * other.mass / distance2.sqrt();
+                }
+            }
+        }
+        diagnostics
+    }
+
+    pub fn total_energy(&self) -> Float {
+        self.kinetic_energy + self.potential_energy
+    }
+}
+
+/// Largest relative deviation of each conserved quantity from its value at the
+/// start of `bodies_path`.
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct Drift {
+    pub energy: Float,
+    pub momentum: Float,
+    pub angular_momentum: Float,
+}
+
+impl Drift {
+    /// Momenta are compared against the sum of their per-body magnitudes, since
+    /// the totals are often zero in the centre-of-mass frame.
+    pub fn measure(diagnostics: &[Diagnostics], first_bodies: &[Body]) -> Drift {
+        let mut drift = Drift {
+            energy: 0.0,
+            momentum: 0.0,
+            angular_momentum: 0.0,
+        };
+        let Some(first) = diagnostics.first() else {
+            return drift;
+        };
+        let scale = |value: Float| if value == 0.0 { 1.0 } else { value };
+        let energy_scale = scale(first.total_energy().abs());
+        let momentum_scale = scale(
+            first_bodies
+                .iter()
+                .map(|body| body.mass * body.vel.magnitude())
+                .sum(),
+        );
+        let angular_momentum_scale = scale(
+            first_bodies
+                .iter()
+                .map(|body| body.mass * body.pos.cross(body.vel).magnitude())
+                .sum(),
+        );
+        for entry in diagnostics {
+            drift.energy = drift
+                .energy
+                .max((entry.total_energy() - first.total_energy()).abs() / energy_scale);
+            drift.momentum = drift
+                .momentum
+                .max((entry.momentum - first.momentum).magnitude() / momentum_scale);
+            drift.angular_momentum = drift.angular_momentum.max(
+                (entry.angular_momentum - first.angular_momentum).magnitude()
+                    / angular_momentum_scale,
+            );
+        }
+        drift
+    }
+}
+
+impl Universe {
+    /// Time of the entry of `bodies_path` at `index`, where 0.0 is the start of
+    /// the animation and negative times belong to the light history.
+    pub fn step_time(&self, index: usize) -> Float {
+        index as Float * self.dt - self.light_simulation_length()
+    }
+
+    /// The potential energy includes that of the external forces.
+    pub fn diagnostics(&self) -> Vec<Diagnostics> {
+        self.bodies_path
+            .steps()
+            .enumerate()
+            .map(|(i, step)| {
+                let mut diagnostics = Diagnostics::measure(
+                    self.step_time(i),
+                    &step.bodies(),
+                    self.gravity_strength,
+                    self.softening,
+                );
+                for force in &self.external_forces {
+                    for (&pos, &mass) in step.pos.iter().zip(step.mass) {
+                        if let Some(potential) =
+                            force.potential(pos, self.gravity_strength, self.softening)
+                        {
+                            diagnostics.potential_energy += mass * potential;
+                        }
+                    }
+                }
+                diagnostics
+            })
+            .collect()
+    }
+
+    pub fn drift(&self) -> Drift {
+        match self.bodies_path.first() {
+            Some(first) => Drift::measure(&self.diagnostics(), &first.bodies()),
+            None => Drift::measure(&[], &[]),
+        }
+    }
+}
+
+pub fn write_diagnostics_csv(
+    diagnostics: &[Diagnostics],
+    mut f: impl Write,
+) -> std::io::Result<()> {
+    writeln!(
+        f,
+        "time,kinetic_energy,potential_energy,total_energy,momentum_x,momentum_y,momentum_z,angular_momentum_x,angular_momentum_y,angular_momentum_z"
+    )?;
+    for entry in diagnostics {
+        writeln!(
+            f,
+            "{},{},{},{},{},{},{},{},{},{}",
+            entry.time,
+            entry.kinetic_energy,
+            entry.potential_energy,
+            entry.total_energy(),
+            entry.momentum.x,
+            entry.momentum.y,
+            entry.momentum.z,
+            entry.angular_momentum.x,
+            entry.angular_momentum.y,
+            entry.angular_momentum.z,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{body, start_conditions};
+    use crate::{Integrator, Universe};
+    use cgmath::vec3;
+
+    #[test]
+    fn leapfrog_conserves_binary_orbit() {
+        let start_conditions = start_conditions(
+            vec![
+                body(vec3(-1.0, 0.0, 0.0), vec3(0.0, -0.35, 0.0), 1.0),
+                body(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.35, 0.0), 1.0),
+            ],
+            Integrator::Leapfrog,
+        );
+        let universe = Universe::new(&start_conditions);
+        let diagnostics = universe.diagnostics();
+        assert_eq!(diagnostics.len(), universe.bodies_path.len());
+        assert_eq!(diagnostics[universe.light_iter_count()].time, 0.0);
+
+        let drift = universe.drift();
+        assert!(drift.energy < 1e-3, "{drift:?}");
+        assert!(drift.momentum < 1e-5, "{drift:?}");
+        assert!(drift.angular_momentum < 1e-4, "{drift:?}");
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{Float, Universe};
+use cgmath::Vector3;
+use std::io::Write;
+
+/// State of one body at one entry of `bodies_path`, as written by the exports.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TrajectoryPoint {
+    pub time: Float,
+    pub id: u32,
+    pub pos: Vector3<Float>,
+    pub vel: Vector3<Float>,
+    pub mass: Float,
+    pub radius: Float,
+}
+
+impl Universe {
+    /// Every stored body state in time order, including the light history
+    /// before the start of the animation.
+    pub fn trajectory_points(&self) -> impl Iterator<Item = TrajectoryPoint> + '_ {
+        self.bodies_path
+            .steps()
+            .enumerate()
+            .flat_map(move |(i, step)| {
+                let time = self.step_time(i);
+                (0..step.len()).map(move |b| TrajectoryPoint {
+                    time,
+                    id: step.ids[b],
+                    pos: step.pos[b],
+                    vel: step.vel[b],
+                    mass: step.mass[b],
+                    radius: step.radius[b],
+                })
+            })
+    }
+}
+
+pub fn write_trajectories_csv(universe: &Universe, mut f: impl Write) -> std::io::Result<()> {
+    writeln!(f, "time,id,x,y,z,vx,vy,vz,mass,radius")?;
+    for point in universe.trajectory_points() {
+        writeln!(
+            f,
+            "{},{},{},{},{},{},{},{},{},{}",
+            point.time,
+            point.id,
+            point.pos.x,
+            point.pos.y,
+            point.pos.z,
+            point.vel.x,
+            point.vel.y,
+            point.vel.z,
+            point.mass,
+            point.radius,
+        )?;
+    }
+    Ok(())
+}
+
+/// One JSON object per line, in the same order as the CSV export.
+pub fn write_trajectories_jsonl(universe: &Universe, mut f: impl Write) -> std::io::Result<()> {
+    for point in universe.trajectory_points() {
+        serde_json::to_writer(&mut f, &point)?;
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+    use crate::{CollisionMode, Integrator};
+    use cgmath::{vec3, InnerSpace};
+
+    #[test]
+    fn exports_list_every_state() {
+        let mut start_conditions = start_conditions(
+            vec![
+                body(vec3(-1.0, 0.0, 0.0), vec3(2.0, 0.0, 0.0), 1.0),
+                body(vec3(1.0, 0.0, 0.0), vec3(-2.0, 0.0, 0.0), 1.0),
+            ],
+            Integrator::Leapfrog,
+        );
+        start_conditions.softening = 0.05;
+        start_conditions.collisions = CollisionMode::Merge;
+        let universe = Universe::new(&start_conditions);
+        let states: usize = universe.bodies_path.steps().map(|step| step.len()).sum();
+
+        let mut csv = vec![];
+        write_trajectories_csv(&universe, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 1 + states);
+        assert_eq!(
+            csv.lines().nth(1).unwrap().split(',').count(),
+            csv.lines().next().unwrap().split(',').count()
+        );
+
+        let mut jsonl = vec![];
+        write_trajectories_jsonl(&universe, &mut jsonl).unwrap();
+        let points: Vec<TrajectoryPoint> = String::from_utf8(jsonl)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(points.len(), states);
+        for (read, point) in points.iter().zip(universe.trajectory_points()) {
+            assert_eq!(read.id, point.id);
+            assert!((read.pos - point.pos).magnitude() < 1e-6);
+        }
+        assert_eq!(points.last().unwrap().mass, 2.0);
+        assert_eq!(points[1].time, -universe.light_simulation_length());
+    }
+}
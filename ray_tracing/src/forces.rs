@@ -0,0 +1,250 @@
+use crate::{Body, Float};
+use cgmath::{InnerSpace, Vector3, Zero};
+
+/// Pull that a point `mass` at `offset` from a photon has on its direction,
+/// the same as in `trace_ray`.
+pub(crate) fn light_tug(
+    offset: Vector3<Float>,
+    mass: Float,
+    gravity_strength: Float,
+    light_speed: Float,
+) -> Vector3<Float> {
+    let a = (4.0 * gravity_strength * mass) / ((light_speed * light_speed) * offset.magnitude());
+    a * offset.normalize()
+}
+
+/// Force acting everywhere on top of the gravity between bodies. Scripted
+/// bodies ignore them.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ExternalForce {
+    /// The same acceleration everywhere. It doesn't bend light, see
+    /// `light_tug`.
+    ConstantField { acceleration: Vector3<Float> },
+    /// Mass fixed at `pos` that isn't a body, so it can't be hit or moved.
+    PointMass { pos: Vector3<Float>, mass: Float },
+    /// Potential `½ v² ln(r² + core_radius²)` around `center`, whose orbits
+    /// have a flat rotation curve at `circular_speed` far outside the core.
+    LogarithmicHalo {
+        center: Vector3<Float>,
+        circular_speed: Float,
+        core_radius: Float,
+    },
+    /// Slows bodies down by `coefficient` times their velocity.
+    Drag { coefficient: Float },
+}
+
+impl ExternalForce {
+    /// Acceleration of `body`, with point masses softened by `softening`.
+    pub fn acceleration(
+        &self,
+        body: &Body,
+        gravity_strength: Float,
+        softening: Float,
+    ) -> Vector3<Float> {
+        self.acceleration_at(body.pos, body.vel, gravity_strength, softening)
+    }
+
+    /// Acceleration of something at `pos` moving with `vel`.
+    pub(crate) fn acceleration_at(
+        &self,
+        pos: Vector3<Float>,
+        vel: Vector3<Float>,
+        gravity_strength: Float,
+        softening: Float,
+    ) -> Vector3<Float> {
+        match *self {
+            ExternalForce::ConstantField { acceleration } => acceleration,
+            ExternalForce::PointMass { pos: at, mass } => {
+                let offset = at - pos;
+                let distance2 = offset.magnitude2() + softening * softening;
+                offset * (gravity_strength * mass / (distance2 * distance2.sqrt()))
+            }
+            ExternalForce::LogarithmicHalo {
+                center,
+                circular_speed,
+                core_radius,
+            } => {
+                let offset = pos - center;
+                offset
+                    * (-circular_speed * circular_speed
+                        / (offset.magnitude2() + core_radius * core_radius))
+            }
+            ExternalForce::Drag { coefficient } => vel * -coefficient,
+        }
+    }
+
+    /// Potential energy per unit mass at `pos`, for forces that have one.
+    pub fn potential(
+        &self,
+        pos: Vector3<Float>,
+        gravity_strength: Float,
+        softening: Float,
+    ) -> Option<Float> {
+        match *self {
+            ExternalForce::ConstantField { acceleration } => Some(-acceleration.dot(pos)),
+            ExternalForce::PointMass { pos: at, mass } => Some(
+                -gravity_strength * mass / ((pos - at).magnitude2() + softening * softening).sqrt(),
+            ),
+            ExternalForce::LogarithmicHalo {
+                center,
+                circular_speed,
+                core_radius,
+            } => Some(
+                0.5 * circular_speed
+                    * circular_speed
+                    * ((pos - center).magnitude2() + core_radius * core_radius).ln(),
+            ),
+            ExternalForce::Drag { .. } => None,
+        }
+    }
+
+    /// Whether `light_tug` is ever anything but zero.
+    pub fn bends_light(&self) -> bool {
+        !matches!(
+            self,
+            ExternalForce::ConstantField { .. } | ExternalForce::Drag { .. }
+        )
+    }
+
+    /// Pull on the direction of a photon at `pos`, scaled the way bodies pull
+    /// on light: the acceleration a slow body would feel there, times
+    /// `4 r / c²` where `r` is the distance to the centre of the field. For a
+    /// point mass that is exactly the pull of a body, and every field scales
+    /// with `1 / c²`. A constant field has no centre and drag isn't gravity,
+    /// so neither of them bends light.
+    pub fn light_tug(
+        &self,
+        pos: Vector3<Float>,
+        gravity_strength: Float,
+        light_speed: Float,
+    ) -> Vector3<Float> {
+        match *self {
+            ExternalForce::PointMass { pos: at, mass } => {
+                light_tug(at - pos, mass, gravity_strength, light_speed)
+            }
+            ExternalForce::LogarithmicHalo {
+                center,
+                circular_speed,
+                core_radius,
+            } => {
+                let offset = pos - center;
+                let distance2 = offset.magnitude2();
+                offset
+                    * (-4.0 * circular_speed * circular_speed * distance2.sqrt()
+                        / ((distance2 + core_radius * core_radius) * light_speed * light_speed))
+            }
+            ExternalForce::ConstantField { .. } | ExternalForce::Drag { .. } => Vector3::zero(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+    use crate::{Integrator, Universe};
+    use cgmath::vec3;
+
+    fn universe(bodies: Vec<Body>, forces: Vec<ExternalForce>) -> Universe {
+        let mut start_conditions = start_conditions(bodies, Integrator::RungeKutta4);
+        start_conditions.external_forces = forces;
+        start_conditions.animation_length = 5.0;
+        Universe::new(&start_conditions)
+    }
+
+    #[test]
+    fn bodies_move_as_the_forces_say() {
+        let gravity = vec3(0.0, -2.0, 0.0);
+        let falling = universe(
+            vec![body(vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 0.0)],
+            vec![ExternalForce::ConstantField {
+                acceleration: gravity,
+            }],
+        );
+        let last = falling.bodies_path.last().unwrap();
+        assert!((last.pos[0] - vec3(5.0, -25.0, 0.0)).magnitude() < 1e-3);
+
+        let dragged = universe(
+            vec![body(vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 0.0)],
+            vec![ExternalForce::Drag { coefficient: 0.5 }],
+        );
+        let last = dragged.bodies_path.last().unwrap();
+        assert!((last.vel[0].x - (-2.5 as Float).exp()).abs() < 1e-4);
+
+        // Circular orbit in the flat part of the rotation curve.
+        let halo = universe(
+            vec![body(vec3(10.0, 0.0, 0.0), vec3(0.0, 0.5, 0.0), 0.0)],
+            vec![ExternalForce::LogarithmicHalo {
+                center: vec3(0.0, 0.0, 0.0),
+                circular_speed: 0.5,
+                core_radius: 0.0,
+            }],
+        );
+        for step in halo.bodies_path.steps() {
+            assert!((step.pos[0].magnitude() - 10.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn point_mass_acts_like_a_fixed_body() {
+        let particle = body(vec3(2.0, 1.0, 0.0), vec3(0.0, 0.3, 0.1), 0.0);
+        let external = universe(
+            vec![particle.clone()],
+            vec![ExternalForce::PointMass {
+                pos: vec3(0.0, 0.0, 0.0),
+                mass: 1.0,
+            }],
+        );
+        let mut fixed = body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0);
+        fixed.motion = crate::Motion::Fixed;
+        let internal = universe(vec![particle, fixed], vec![]);
+        let (a, b) = (
+            external.bodies_path.last().unwrap(),
+            internal.bodies_path.last().unwrap(),
+        );
+        assert!((a.pos[0] - b.pos[0]).magnitude() < 1e-5);
+
+        let force = ExternalForce::PointMass {
+            pos: vec3(0.0, 0.0, 3.0),
+            mass: 2.0,
+        };
+        let photon = vec3(0.5, 0.0, 0.0);
+        let tug = force.light_tug(photon, 1.5, 2.0);
+        assert_eq!(tug, light_tug(vec3(-0.5, 0.0, 3.0), 2.0, 1.5, 2.0));
+        assert!(tug.z > 0.0 && tug.x < 0.0);
+    }
+
+    #[test]
+    fn fields_bend_light_like_bodies() {
+        let pos = vec3(0.0, 3.0, 4.0);
+        let (gravity_strength, speed) = (1.5, 0.8);
+        for light_speed in [1.0, 3.0] {
+            // Outside its core a halo has the pull on light of a point mass
+            // with the same circular speed there.
+            let halo = ExternalForce::LogarithmicHalo {
+                center: vec3(0.0, 0.0, 0.0),
+                circular_speed: speed,
+                core_radius: 0.0,
+            };
+            let mass = speed * speed * 5.0 / gravity_strength;
+            let point_mass = ExternalForce::PointMass {
+                pos: vec3(0.0, 0.0, 0.0),
+                mass,
+            };
+            let (a, b) = (
+                halo.light_tug(pos, gravity_strength, light_speed),
+                point_mass.light_tug(pos, gravity_strength, light_speed),
+            );
+            assert!((a - b).magnitude() < 1e-5);
+            let expected = 4.0 * speed * speed / (light_speed * light_speed);
+            assert!((a.magnitude() - expected).abs() < 1e-5);
+            assert!(a.dot(pos) < 0.0);
+        }
+
+        let field = ExternalForce::ConstantField {
+            acceleration: vec3(0.0, -1.0, 0.0),
+        };
+        assert!(!field.bends_light());
+        assert_eq!(field.light_tug(pos, 1.0, 1.0), Vector3::zero());
+    }
+}
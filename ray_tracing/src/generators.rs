@@ -0,0 +1,512 @@
+use crate::{orbits::BodyRef, to_f32, Body, Float, Motion, SimulationError};
+use cgmath::{vec3, InnerSpace, Vector3, Zero};
+use simple_video::ColorF32;
+
+/// SplitMix64, so that a seed expands to the same bodies on every platform
+/// and in either precision.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn uniform(&mut self) -> Float {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) as Float
+    }
+
+    fn range(&mut self, min: Float, max: Float) -> Float {
+        min + (max - min) * self.uniform()
+    }
+
+    /// Uniform in `[min, max]` with a density proportional to `x^slope`.
+    fn power_law(&mut self, min: Float, max: Float, slope: Float) -> Float {
+        let u = self.uniform();
+        let k = slope + 1.0;
+        if k.abs() < 1e-6 {
+            min * (max / min).powf(u)
+        } else {
+            (min.powf(k) + u * (max.powf(k) - min.powf(k))).powf(1.0 / k)
+        }
+    }
+
+    fn unit_vector(&mut self) -> Vector3<Float> {
+        let z = self.range(-1.0, 1.0);
+        let (sin, cos) = (std::f64::consts::TAU as Float * self.uniform()).sin_cos();
+        let r = (1.0 - z * z).sqrt();
+        vec3(r * cos, r * sin, z)
+    }
+}
+
+/// How the masses of generated bodies are drawn.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum MassDistribution {
+    /// Every body has the same mass.
+    Equal(Float),
+    Uniform {
+        min: Float,
+        max: Float,
+    },
+    /// `dN/dm ∝ m^-exponent` between `min` and `max`, e.g. 2.35 for Salpeter.
+    PowerLaw {
+        min: Float,
+        max: Float,
+        exponent: Float,
+    },
+}
+
+impl MassDistribution {
+    fn sample(&self, rng: &mut Rng) -> Float {
+        match *self {
+            MassDistribution::Equal(mass) => mass,
+            MassDistribution::Uniform { min, max } => rng.range(min, max),
+            MassDistribution::PowerLaw { min, max, exponent } => rng.power_law(min, max, -exponent),
+        }
+    }
+}
+
+/// Colors of the innermost and outermost generated bodies, blended in between.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ColorGradient {
+    pub inner: ColorF32,
+    pub outer: ColorF32,
+}
+
+impl ColorGradient {
+    fn at(&self, fraction: Float) -> ColorF32 {
+        let t = to_f32(fraction.clamp(0.0, 1.0));
+        ColorF32 {
+            r: self.inner.r + (self.outer.r - self.inner.r) * t,
+            g: self.inner.g + (self.outer.g - self.inner.g) * t,
+            b: self.inner.b + (self.outer.b - self.inner.b) * t,
+        }
+    }
+}
+
+/// Spatial layout and motion of a generated group of bodies.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Shape {
+    /// Flat annulus of bodies on circular orbits around the centre.
+    Ring {
+        inner_radius: Float,
+        outer_radius: Float,
+    },
+    /// Disk with a surface density falling off as `r^-density_exponent` and
+    /// bodies on circular orbits around the centre plus the disk mass inside them.
+    KeplerianDisk {
+        inner_radius: Float,
+        outer_radius: Float,
+        #[serde(default)]
+        thickness: Float,
+        #[serde(default)]
+        density_exponent: Float,
+    },
+    /// Self-gravitating Plummer sphere in equilibrium, with velocities drawn
+    /// for its own mass. Cut off at 99.9% of that mass.
+    PlummerSphere { scale_radius: Float },
+    /// Bodies spread evenly through a ball, with velocities pointing anywhere
+    /// and speeds up to `max_speed`.
+    RandomCloud {
+        radius: Float,
+        #[serde(default)]
+        max_speed: Float,
+    },
+}
+
+impl Shape {
+    /// Why the shape can't be generated, if it can't.
+    fn problem(&self) -> Option<&'static str> {
+        match *self {
+            Shape::Ring {
+                inner_radius,
+                outer_radius,
+            }
+            | Shape::KeplerianDisk {
+                inner_radius,
+                outer_radius,
+                ..
+            } => {
+                if inner_radius <= 0.0 {
+                    Some("inner_radius must be positive")
+                } else if outer_radius < inner_radius {
+                    Some("outer_radius must not be smaller than inner_radius")
+                } else {
+                    None
+                }
+            }
+            Shape::PlummerSphere { scale_radius } => {
+                (scale_radius <= 0.0).then_some("scale_radius must be positive")
+            }
+            Shape::RandomCloud { radius, .. } => {
+                (radius <= 0.0).then_some("radius must be positive")
+            }
+        }
+    }
+}
+
+/// How far `r` is from `inner_radius` towards `outer_radius`, 0 for a band
+/// without width.
+fn radial_fraction(r: Float, inner_radius: Float, outer_radius: Float) -> Float {
+    if outer_radius > inner_radius {
+        (r - inner_radius) / (outer_radius - inner_radius)
+    } else {
+        0.0
+    }
+}
+
+/// Group of bodies that the scene file describes by its shape instead of one
+/// by one. Expanded into bodies when the `Universe` is built; the same `seed`
+/// always gives the same bodies.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Generator {
+    pub shape: Shape,
+    pub count: usize,
+    pub seed: u64,
+    pub mass: MassDistribution,
+    /// Radius of each generated body.
+    pub radius: Float,
+    /// From the centre of the group to its outer edge.
+    pub colors: ColorGradient,
+    /// Body the group is centred on and moves along with. Rings and disks
+    /// orbit it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub center: Option<BodyRef>,
+    /// Normal of the plane of rings and disks; they turn counterclockwise
+    /// when seen from this side.
+    #[serde(default = "Generator::default_normal")]
+    pub normal: Vector3<Float>,
+}
+
+impl Generator {
+    fn default_normal() -> Vector3<Float> {
+        Vector3::unit_y()
+    }
+
+    /// The generated bodies, without ids. `center` is the body the group is
+    /// centred on, if any.
+    pub fn generate(&self, center: Option<&Body>, gravity_strength: Float) -> Vec<Body> {
+        let mut rng = Rng::new(self.seed);
+        let normal = self.normal.normalize();
+        let helper = if normal.x.abs() < 0.9 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        let u = normal.cross(helper).normalize();
+        let w = normal.cross(u);
+        let center_mass = center.map_or(0.0, |center| center.mass);
+
+        // Mass, offset, velocity and color fraction of every body.
+        let mut bodies: Vec<(Float, Vector3<Float>, Vector3<Float>, Float)> = (0..self.count)
+            .map(|_| {
+                let mass = self.mass.sample(&mut rng);
+                let (pos, fraction) = match self.shape {
+                    Shape::Ring {
+                        inner_radius,
+                        outer_radius,
+                    } => {
+                        let r = rng.range(inner_radius, outer_radius);
+                        let (sin, cos) = (std::f64::consts::TAU as Float * rng.uniform()).sin_cos();
+                        (
+                            (u * cos + w * sin) * r,
+                            radial_fraction(r, inner_radius, outer_radius),
+                        )
+                    }
+                    Shape::KeplerianDisk {
+                        inner_radius,
+                        outer_radius,
+                        thickness,
+                        density_exponent,
+                    } => {
+                        let r = rng.power_law(inner_radius, outer_radius, 1.0 - density_exponent);
+                        let (sin, cos) = (std::f64::consts::TAU as Float * rng.uniform()).sin_cos();
+                        let height = rng.range(-0.5, 0.5) * thickness;
+                        (
+                            (u * cos + w * sin) * r + normal * height,
+                            radial_fraction(r, inner_radius, outer_radius),
+                        )
+                    }
+                    Shape::PlummerSphere { scale_radius } => {
+                        let enclosed = (1.0 - rng.uniform()) * 0.999;
+                        let r = scale_radius / (enclosed.powf(-2.0 / 3.0) - 1.0).sqrt();
+                        (rng.unit_vector() * r, enclosed)
+                    }
+                    Shape::RandomCloud { radius, .. } => {
+                        let r = radius * rng.uniform().cbrt();
+                        (rng.unit_vector() * r, r / radius)
+                    }
+                };
+                (mass, pos, Vector3::zero(), fraction)
+            })
+            .collect();
+
+        let total_mass: Float = bodies.iter().map(|&(mass, ..)| mass).sum();
+        match self.shape {
+            Shape::Ring { .. } => {
+                for (_, pos, vel, _) in &mut bodies {
+                    let r = pos.magnitude();
+                    *vel = normal.cross(*pos / r) * (gravity_strength * center_mass / r).sqrt();
+                }
+            }
+            Shape::KeplerianDisk { .. } => {
+                let mut order: Vec<usize> = (0..bodies.len()).collect();
+                order.sort_by(|&a, &b| {
+                    bodies[a]
+                        .1
+                        .magnitude2()
+                        .total_cmp(&bodies[b].1.magnitude2())
+                });
+                let mut enclosed = center_mass;
+                for index in order {
+                    let (mass, pos, vel, _) = &mut bodies[index];
+                    let planar = *pos - normal * normal.dot(*pos);
+                    let r = planar.magnitude();
+                    *vel = normal.cross(planar / r) * (gravity_strength * enclosed / r).sqrt();
+                    enclosed += *mass;
+                }
+            }
+            Shape::PlummerSphere { scale_radius } => {
+                for (_, pos, vel, _) in &mut bodies {
+                    // Aarseth, Hénon & Wielen (1974) rejection sampling of
+                    // the speed as a fraction of the escape speed.
+                    let q = loop {
+                        let q = rng.uniform();
+                        if rng.uniform() * 0.1 < q * q * (1.0 - q * q).powf(3.5) {
+                            break q;
+                        }
+                    };
+                    let escape = (2.0 * gravity_strength * total_mass).sqrt()
+                        * (pos.magnitude2() + scale_radius * scale_radius).powf(-0.25);
+                    *vel = rng.unit_vector() * (q * escape);
+                }
+            }
+            Shape::RandomCloud { max_speed, .. } => {
+                for (_, _, vel, _) in &mut bodies {
+                    *vel = rng.unit_vector() * (max_speed * rng.uniform());
+                }
+            }
+        }
+
+        let (origin, drift) = center.map_or((Vector3::zero(), Vector3::zero()), |center| {
+            (center.pos, center.vel)
+        });
+        bodies
+            .into_iter()
+            .map(|(mass, pos, vel, fraction)| Body {
+                id: Body::UNASSIGNED_ID,
+                name: None,
+                pos: origin + pos,
+                vel: drift + vel,
+                radius: self.radius,
+                color: self.colors.at(fraction),
+                mass,
+                softening: None,
+                motion: Motion::Dynamic,
+                orbit: None,
+            })
+            .collect()
+    }
+}
+
+/// Bodies of every generator, in order, centred on their centre in `bodies`.
+pub(crate) fn generate_bodies(
+    generators: &[Generator],
+    bodies: &[Body],
+    gravity_strength: Float,
+) -> Result<Vec<Body>, SimulationError> {
+    let mut generated = vec![];
+    for (index, generator) in generators.iter().enumerate() {
+        if let Some(problem) = generator.shape.problem() {
+            return Err(SimulationError::InvalidShape {
+                generator: index,
+                problem,
+            });
+        }
+        let center = match &generator.center {
+            Some(center) => {
+                Some(
+                    center
+                        .find(bodies)
+                        .ok_or_else(|| SimulationError::UnknownCenter {
+                            generator: index,
+                            center: center.to_string(),
+                        })?,
+                )
+            }
+            None => None,
+        };
+        generated
+            .extend(generator.generate(center.map(|center| &bodies[center]), gravity_strength));
+    }
+    Ok(generated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+    use crate::Integrator;
+    use cgmath::MetricSpace;
+
+    fn generator(shape: Shape) -> Generator {
+        Generator {
+            shape,
+            count: 2000,
+            seed: 7,
+            mass: MassDistribution::PowerLaw {
+                min: 0.001,
+                max: 0.01,
+                exponent: 2.35,
+            },
+            radius: 0.01,
+            colors: ColorGradient {
+                inner: ColorF32 {
+                    r: 1.0,
+                    g: 1.0,
+                    b: 1.0,
+                },
+                outer: ColorF32 {
+                    r: 1.0,
+                    g: 0.0,
+                    b: 0.0,
+                },
+            },
+            center: Some(BodyRef::Name("planet".to_string())),
+            normal: vec3(0.0, 0.0, 1.0),
+        }
+    }
+
+    #[test]
+    fn expansion_is_deterministic_per_seed() {
+        let mut planet = body(vec3(1.0, 2.0, 3.0), vec3(0.0, 0.5, 0.0), 1.0);
+        planet.name = Some("planet".to_string());
+        let mut start_conditions = start_conditions(vec![planet], Integrator::Leapfrog);
+        start_conditions.generators = [
+            Shape::Ring {
+                inner_radius: 1.0,
+                outer_radius: 2.0,
+            },
+            Shape::KeplerianDisk {
+                inner_radius: 1.0,
+                outer_radius: 3.0,
+                thickness: 0.1,
+                density_exponent: 1.0,
+            },
+            Shape::PlummerSphere { scale_radius: 1.0 },
+            Shape::RandomCloud {
+                radius: 2.0,
+                max_speed: 0.1,
+            },
+        ]
+        .into_iter()
+        .map(generator)
+        .collect();
+
+        let bodies = start_conditions.initial_bodies().unwrap();
+        assert_eq!(bodies.len(), 1 + 4 * 2000);
+        let mut ids: Vec<u32> = bodies.iter().map(|body| body.id).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), bodies.len());
+
+        let again = start_conditions.initial_bodies().unwrap();
+        for (a, b) in bodies.iter().zip(&again) {
+            assert_eq!((a.pos, a.vel, a.mass), (b.pos, b.vel, b.mass));
+        }
+        start_conditions.generators[3].seed = 8;
+        let reseeded = start_conditions.initial_bodies().unwrap();
+        assert_eq!(bodies[6000].pos, reseeded[6000].pos);
+        assert_ne!(bodies[6001].pos, reseeded[6001].pos);
+    }
+
+    #[test]
+    fn generated_bodies_follow_their_shape() {
+        let mut planet = body(vec3(1.0, 2.0, 3.0), vec3(0.0, 0.5, 0.0), 1.0);
+        planet.name = Some("planet".to_string());
+
+        let ring = generator(Shape::Ring {
+            inner_radius: 1.0,
+            outer_radius: 2.0,
+        });
+        for body in ring.generate(Some(&planet), 2.0) {
+            let offset = body.pos - planet.pos;
+            let r = offset.magnitude();
+            assert!((1.0..=2.0).contains(&r) && offset.z.abs() < 1e-6);
+            let speed = (body.vel - planet.vel).magnitude();
+            assert!((speed - (2.0 / r).sqrt()).abs() < 1e-4);
+            assert!(offset.cross(body.vel - planet.vel).z > 0.0);
+            assert!((0.001..=0.01).contains(&body.mass));
+        }
+
+        // Half of a Plummer sphere's mass lies within 1.305 scale radii.
+        let mut sphere = generator(Shape::PlummerSphere { scale_radius: 1.0 });
+        sphere.mass = MassDistribution::Equal(1.0 / 2000.0);
+        let mut radii: Vec<Float> = sphere
+            .generate(Some(&planet), 1.0)
+            .iter()
+            .map(|body| body.pos.distance(planet.pos))
+            .collect();
+        radii.sort_by(Float::total_cmp);
+        assert!((radii[1000] - 1.305).abs() < 0.1, "{}", radii[1000]);
+    }
+
+    #[test]
+    fn thin_bands_take_the_inner_color_and_empty_shapes_are_rejected() {
+        let mut planet = body(vec3(1.0, 2.0, 3.0), vec3(0.0, 0.5, 0.0), 1.0);
+        planet.name = Some("planet".to_string());
+        for shape in [
+            Shape::Ring {
+                inner_radius: 1.5,
+                outer_radius: 1.5,
+            },
+            Shape::KeplerianDisk {
+                inner_radius: 1.5,
+                outer_radius: 1.5,
+                thickness: 0.0,
+                density_exponent: 1.0,
+            },
+        ] {
+            for body in generator(shape).generate(Some(&planet), 1.0) {
+                assert_eq!((body.color.r, body.color.g), (1.0, 1.0));
+                assert!(body.vel.x.is_finite() && body.vel.y.is_finite());
+            }
+        }
+
+        let mut start_conditions = start_conditions(vec![planet], Integrator::Leapfrog);
+        for shape in [
+            Shape::Ring {
+                inner_radius: 0.0,
+                outer_radius: 1.0,
+            },
+            Shape::KeplerianDisk {
+                inner_radius: 2.0,
+                outer_radius: 1.0,
+                thickness: 0.0,
+                density_exponent: 1.0,
+            },
+            Shape::PlummerSphere { scale_radius: 0.0 },
+            Shape::RandomCloud {
+                radius: 0.0,
+                max_speed: 0.1,
+            },
+        ] {
+            start_conditions.generators = vec![
+                generator(Shape::PlummerSphere { scale_radius: 1.0 }),
+                generator(shape),
+            ];
+            assert!(matches!(
+                start_conditions.initial_bodies(),
+                Err(SimulationError::InvalidShape { generator: 1, .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{
+    snapshot::{
+        invalid_data, read_float, read_json, read_u32, read_u64, write_float, write_json,
+        write_u32, write_u64,
+    },
+    Body, Float, Motion,
+};
+use cgmath::Vector3;
+use simple_video::ColorF32;
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
+
+/// Parts of a body that stay the same for its whole life.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct BodyInfo {
+    name: Option<String>,
+    softening: Option<Float>,
+    motion: Motion,
+}
+
+/// Body states of every simulation step in one flat, time-ordered buffer.
+///
+/// Each quantity is its own contiguous array, and the bodies of step `i` live at
+/// `offsets[i]..offsets[i + 1]` in all of them, so steps can hold different
+/// numbers of bodies once they start merging.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct BodyHistory {
+    offsets: Vec<usize>,
+    ids: Vec<u32>,
+    pos: Vec<Vector3<Float>>,
+    vel: Vec<Vector3<Float>>,
+    radius: Vec<Float>,
+    color: Vec<ColorF32>,
+    mass: Vec<Float>,
+    info: BTreeMap<u32, BodyInfo>,
+}
+
+/// The bodies of one step of a `BodyHistory`.
+#[derive(Clone, Copy, Debug)]
+pub struct Step<'a> {
+    history: &'a BodyHistory,
+    pub ids: &'a [u32],
+    pub pos: &'a [Vector3<Float>],
+    pub vel: &'a [Vector3<Float>],
+    pub radius: &'a [Float],
+    pub color: &'a [ColorF32],
+    pub mass: &'a [Float],
+}
+
+impl BodyHistory {
+    /// Room for `steps` steps of `bodies` bodies each without reallocating.
+    pub fn with_capacity(steps: usize, bodies: usize) -> BodyHistory {
+        let states = steps * bodies;
+        let mut offsets = Vec::with_capacity(steps + 1);
+        offsets.push(0);
+        BodyHistory {
+            offsets,
+            ids: Vec::with_capacity(states),
+            pos: Vec::with_capacity(states),
+            vel: Vec::with_capacity(states),
+            radius: Vec::with_capacity(states),
+            color: Vec::with_capacity(states),
+            mass: Vec::with_capacity(states),
+            info: BTreeMap::new(),
+        }
+    }
+
+    /// Number of steps.
+    pub fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, bodies: &[Body]) {
+        if self.offsets.is_empty() {
+            self.offsets.push(0);
+        }
+        for body in bodies {
+            self.ids.push(body.id);
+            self.pos.push(body.pos);
+            self.vel.push(body.vel);
+            self.radius.push(body.radius);
+            self.color.push(body.color);
+            self.mass.push(body.mass);
+            self.info.entry(body.id).or_insert_with(|| BodyInfo {
+                name: body.name.clone(),
+                softening: body.softening,
+                motion: body.motion.clone(),
+            });
+        }
+        self.offsets.push(self.ids.len());
+    }
+
+    /// Reverses the order of the steps, keeping the order of bodies within
+    /// each step. Lets a history that was recorded backwards in time be
+    /// stored chronologically without shifting it once per step.
+    pub fn reverse(&mut self) {
+        fn reverse_blocks<T>(values: &mut [T], offsets: &[usize]) {
+            values.reverse();
+            let total = values.len();
+            for window in offsets.windows(2) {
+                values[total - window[1]..total - window[0]].reverse();
+            }
+        }
+        reverse_blocks(&mut self.ids, &self.offsets);
+        reverse_blocks(&mut self.pos, &self.offsets);
+        reverse_blocks(&mut self.vel, &self.offsets);
+        reverse_blocks(&mut self.radius, &self.offsets);
+        reverse_blocks(&mut self.color, &self.offsets);
+        reverse_blocks(&mut self.mass, &self.offsets);
+
+        let total = self.ids.len();
+        self.offsets.reverse();
+        for offset in &mut self.offsets {
+            *offset = total - *offset;
+        }
+    }
+
+    pub fn step(&self, index: usize) -> Step<'_> {
+        let range = self.offsets[index]..self.offsets[index + 1];
+        Step {
+            history: self,
+            ids: &self.ids[range.clone()],
+            pos: &self.pos[range.clone()],
+            vel: &self.vel[range.clone()],
+            radius: &self.radius[range.clone()],
+            color: &self.color[range.clone()],
+            mass: &self.mass[range],
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<Step<'_>> {
+        (index < self.len()).then(|| self.step(index))
+    }
+
+    pub fn first(&self) -> Option<Step<'_>> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<Step<'_>> {
+        self.len().checked_sub(1).map(|index| self.step(index))
+    }
+
+    pub fn steps(&self) -> impl Iterator<Item = Step<'_>> {
+        (0..self.len()).map(|index| self.step(index))
+    }
+
+    /// Name given to the body with `id`, if any.
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.info.get(&id)?.name.as_deref()
+    }
+
+    /// Id of the first body called `name`.
+    pub fn find_name(&self, name: &str) -> Option<u32> {
+        self.info
+            .iter()
+            .find(|(_, info)| info.name.as_deref() == Some(name))
+            .map(|(&id, _)| id)
+    }
+
+    /// Binary layout used by universe snapshots: the step and state counts,
+    /// the step offsets, each per-state array in turn and the per-body info
+    /// as JSON.
+    pub(crate) fn write(&self, mut f: impl Write) -> std::io::Result<()> {
+        write_u64(&mut f, self.len() as u64)?;
+        write_u64(&mut f, self.ids.len() as u64)?;
+        for &offset in &self.offsets {
+            write_u64(&mut f, offset as u64)?;
+        }
+        for &id in &self.ids {
+            write_u32(&mut f, id)?;
+        }
+        for vectors in [&self.pos, &self.vel] {
+            for vector in vectors {
+                write_float(&mut f, vector.x)?;
+                write_float(&mut f, vector.y)?;
+                write_float(&mut f, vector.z)?;
+            }
+        }
+        for &radius in &self.radius {
+            write_float(&mut f, radius)?;
+        }
+        for color in &self.color {
+            for channel in [color.r, color.g, color.b] {
+                f.write_all(&channel.to_be_bytes())?;
+            }
+        }
+        for &mass in &self.mass {
+            write_float(&mut f, mass)?;
+        }
+        write_json(&mut f, &self.info)
+    }
+
+    /// Reads what `write` wrote, with floats of `float_size` bytes.
+    pub(crate) fn read(mut f: impl Read, float_size: u8) -> std::io::Result<BodyHistory> {
+        let steps = read_u64(&mut f)? as usize;
+        let states = read_u64(&mut f)? as usize;
+        let mut history = BodyHistory::with_capacity(0, 0);
+        history.offsets = (0..=steps)
+            .map(|_| Ok(read_u64(&mut f)? as usize))
+            .collect::<std::io::Result<_>>()?;
+        if history
+            .offsets
+            .windows(2)
+            .any(|window| window[0] > window[1])
+            || history.offsets.first() != Some(&0)
+            || history.offsets.last() != Some(&states)
+        {
+            return Err(invalid_data("snapshot step offsets are inconsistent"));
+        }
+
+        history.ids = (0..states)
+            .map(|_| read_u32(&mut f))
+            .collect::<std::io::Result<_>>()?;
+        let mut read_vectors = || {
+            (0..states)
+                .map(|_| {
+                    Ok(Vector3::new(
+                        read_float(&mut f, float_size)?,
+                        read_float(&mut f, float_size)?,
+                        read_float(&mut f, float_size)?,
+                    ))
+                })
+                .collect::<std::io::Result<Vec<_>>>()
+        };
+        history.pos = read_vectors()?;
+        history.vel = read_vectors()?;
+        history.radius = (0..states)
+            .map(|_| read_float(&mut f, float_size))
+            .collect::<std::io::Result<_>>()?;
+        history.color = (0..states)
+            .map(|_| {
+                let mut channels = [0.0; 3];
+                for channel in &mut channels {
+                    let mut bytes = [0; 4];
+                    f.read_exact(&mut bytes)?;
+                    *channel = f32::from_be_bytes(bytes);
+                }
+                let [r, g, b] = channels;
+                Ok(ColorF32 { r, g, b })
+            })
+            .collect::<std::io::Result<_>>()?;
+        history.mass = (0..states)
+            .map(|_| read_float(&mut f, float_size))
+            .collect::<std::io::Result<_>>()?;
+        history.info = read_json(&mut f)?;
+        if let Some(id) = history.ids.iter().find(|id| !history.info.contains_key(id)) {
+            return Err(invalid_data(format!(
+                "snapshot has no info for body id {id}"
+            )));
+        }
+        Ok(history)
+    }
+}
+
+impl<'a> Step<'a> {
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Index of the body with `id` within this step.
+    pub fn find(&self, id: u32) -> Option<usize> {
+        self.ids.iter().position(|&other| other == id)
+    }
+
+    pub fn body(&self, index: usize) -> Body {
+        let id = self.ids[index];
+        let info = &self.history.info[&id];
+        Body {
+            id,
+            name: info.name.clone(),
+            pos: self.pos[index],
+            vel: self.vel[index],
+            radius: self.radius[index],
+            color: self.color[index],
+            mass: self.mass[index],
+            softening: info.softening,
+            motion: info.motion.clone(),
+            orbit: None,
+        }
+    }
+
+    pub fn bodies(&self) -> Vec<Body> {
+        (0..self.len()).map(|index| self.body(index)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::body;
+    use cgmath::vec3;
+
+    fn bodies(ids: &[u32], x: Float) -> Vec<Body> {
+        ids.iter()
+            .map(|&id| Body {
+                id,
+                ..body(vec3(x, id as Float, 0.0), vec3(0.0, 0.0, 0.0), 1.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reverse_keeps_steps_intact() {
+        let mut history = BodyHistory::with_capacity(3, 3);
+        history.push(&bodies(&[0, 1, 2], 0.0));
+        history.push(&bodies(&[0, 2], 1.0));
+        history.push(&bodies(&[2], 2.0));
+        history.reverse();
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.step(0).ids, [2]);
+        assert_eq!(history.step(1).ids, [0, 2]);
+        assert_eq!(history.step(2).ids, [0, 1, 2]);
+        assert_eq!(history.step(1).pos[1], vec3(1.0, 2.0, 0.0));
+        assert_eq!(history.step(2).body(1).pos, vec3(0.0, 1.0, 0.0));
+    }
+}
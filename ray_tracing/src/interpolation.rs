@@ -0,0 +1,309 @@
+use crate::{physics::hermite, Body, Float, Lerp, Universe};
+use cgmath::Vector3;
+use simple_video::ColorF32;
+
+/// How body states between two entries of `bodies_path` are reconstructed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Interpolation {
+    /// Snap to the nearest stored step.
+    Nearest,
+    /// Straight line between the surrounding steps.
+    Linear,
+    /// Cubic Hermite curve through the surrounding steps, using the stored
+    /// velocities as tangents.
+    #[default]
+    Hermite,
+}
+
+impl Interpolation {
+    /// `a` moved towards `b`, which is `h` later, by the fraction `s`. Mass,
+    /// radius and color are not blended and come from the nearer of the two.
+    pub fn interpolate(self, a: &Body, b: &Body, h: Float, s: Float) -> Body {
+        let nearest = if s < 0.5 { a } else { b };
+        match self {
+            Interpolation::Nearest => nearest.clone(),
+            Interpolation::Linear => Body {
+                pos: self.position(a.pos, a.vel, b.pos, b.vel, h, s),
+                vel: Lerp::lerp(a.vel, b.vel, s),
+                ..nearest.clone()
+            },
+            Interpolation::Hermite => Body {
+                pos: self.position(a.pos, a.vel, b.pos, b.vel, h, s),
+                // The derivative of the position curve, as accelerations aren't stored.
+                vel: (a.pos - b.pos) * ((6.0 * s * s - 6.0 * s) / h)
+                    + a.vel * (3.0 * s * s - 4.0 * s + 1.0)
+                    + b.vel * (3.0 * s * s - 2.0 * s),
+                ..nearest.clone()
+            },
+        }
+    }
+
+    /// Just the position that `interpolate` gives.
+    pub(crate) fn position(
+        self,
+        a_pos: Vector3<Float>,
+        a_vel: Vector3<Float>,
+        b_pos: Vector3<Float>,
+        b_vel: Vector3<Float>,
+        h: Float,
+        s: Float,
+    ) -> Vector3<Float> {
+        match self {
+            Interpolation::Nearest if s < 0.5 => a_pos,
+            Interpolation::Nearest => b_pos,
+            Interpolation::Linear => Lerp::lerp(a_pos, b_pos, s),
+            Interpolation::Hermite => hermite(a_pos, a_vel, b_pos, b_vel, h, s),
+        }
+    }
+}
+
+/// What light needs to know of the bodies at each step of its path: the
+/// interpolated positions, and the radius, color and mass of the nearer
+/// stored step. Kept in flat arrays like `BodyHistory`, with step `i` at
+/// `offsets[i]..offsets[i + 1]`.
+#[derive(Clone, Debug, Default)]
+pub struct LightCone {
+    offsets: Vec<usize>,
+    pos: Vec<Vector3<Float>>,
+    radius: Vec<Float>,
+    color: Vec<ColorF32>,
+    mass: Vec<Float>,
+}
+
+/// The bodies of one step of a `LightCone`.
+#[derive(Clone, Copy, Debug)]
+pub struct LightStep<'a> {
+    pub pos: &'a [Vector3<Float>],
+    pub radius: &'a [Float],
+    pub color: &'a [ColorF32],
+    pub mass: &'a [Float],
+}
+
+impl LightCone {
+    pub fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn step(&self, index: usize) -> LightStep<'_> {
+        let range = self.offsets[index]..self.offsets[index + 1];
+        LightStep {
+            pos: &self.pos[range.clone()],
+            radius: &self.radius[range.clone()],
+            color: &self.color[range.clone()],
+            mass: &self.mass[range],
+        }
+    }
+
+    pub fn steps(&self) -> impl Iterator<Item = LightStep<'_>> {
+        (0..self.len()).map(|index| self.step(index))
+    }
+}
+
+impl Universe {
+    /// Index of the stored step at or before `time`, the one after it if
+    /// there is one, and how far `time` is between them.
+    fn surrounding_steps(&self, time: Float) -> (usize, Option<usize>, Float) {
+        let last = self.bodies_path.len() - 1;
+        let position =
+            ((time + self.light_simulation_length()) / self.dt).clamp(0.0, last as Float);
+        let index = (position.floor() as usize).min(last.saturating_sub(1));
+        let after = (index < last).then_some(index + 1);
+        (index, after, position - index as Float)
+    }
+
+    /// Interpolated states of every body at `time`. Bodies that only exist in
+    /// one of the surrounding steps (because of a merger) are taken from that step.
+    pub fn bodies_at_time(&self, time: Float) -> Vec<Body> {
+        let (index, after, s) = self.surrounding_steps(time);
+        let before = self.bodies_path.step(index);
+        let Some(after) = after.map(|after| self.bodies_path.step(after)) else {
+            return before.bodies();
+        };
+
+        let interpolate = |a: usize, b: usize| {
+            self.interpolation
+                .interpolate(&before.body(a), &after.body(b), self.dt, s)
+        };
+        if before.ids == after.ids {
+            return (0..before.len()).map(|i| interpolate(i, i)).collect();
+        }
+        (0..before.len())
+            .map(|a| match after.find(before.ids[a]) {
+                Some(b) => interpolate(a, b),
+                None => before.body(a),
+            })
+            .chain(
+                (0..after.len())
+                    .filter(|&b| before.find(after.ids[b]).is_none())
+                    .map(|b| after.body(b)),
+            )
+            .collect()
+    }
+
+    /// Interpolated state of the body with `id` at `time`.
+    pub fn body_at_time(&self, id: u32, time: Float) -> Option<Body> {
+        self.bodies_at_time(time)
+            .into_iter()
+            .find(|body| body.id == id)
+    }
+
+    /// Bodies as seen by light at each step of its path backwards from `time`,
+    /// in the order `trace_ray` visits them. The same states as
+    /// `bodies_at_time` gives, without building a `Body` for each.
+    pub fn light_cone(&self) -> LightCone {
+        let steps = self.light_iter_count();
+        let bodies = self.bodies_path.first().map_or(0, |step| step.len());
+        let mut cone = LightCone {
+            offsets: Vec::with_capacity(steps + 1),
+            pos: Vec::with_capacity(steps * bodies),
+            radius: Vec::with_capacity(steps * bodies),
+            color: Vec::with_capacity(steps * bodies),
+            mass: Vec::with_capacity(steps * bodies),
+        };
+        cone.offsets.push(0);
+        for i in 1..=steps {
+            let (index, after, s) = self.surrounding_steps(self.time - i as Float * self.dt);
+            let before = self.bodies_path.step(index);
+            let after = after.map(|after| self.bodies_path.step(after));
+            let mut push = |pos, step: &crate::Step, b: usize| {
+                cone.pos.push(pos);
+                cone.radius.push(step.radius[b]);
+                cone.color.push(step.color[b]);
+                cone.mass.push(step.mass[b]);
+            };
+            match after {
+                None => {
+                    for a in 0..before.len() {
+                        push(before.pos[a], &before, a);
+                    }
+                }
+                Some(after) => {
+                    for a in 0..before.len() {
+                        let Some(b) = (if before.ids == after.ids {
+                            Some(a)
+                        } else {
+                            after.find(before.ids[a])
+                        }) else {
+                            push(before.pos[a], &before, a);
+                            continue;
+                        };
+                        let pos = self.interpolation.position(
+                            before.pos[a],
+                            before.vel[a],
+                            after.pos[b],
+                            after.vel[b],
+                            self.dt,
+                            s,
+                        );
+                        if s < 0.5 {
+                            push(pos, &before, a);
+                        } else {
+                            push(pos, &after, b);
+                        }
+                    }
+                    if before.ids != after.ids {
+                        for b in 0..after.len() {
+                            if before.find(after.ids[b]).is_none() {
+                                push(after.pos[b], &after, b);
+                            }
+                        }
+                    }
+                }
+            }
+            cone.offsets.push(cone.pos.len());
+        }
+        cone
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+    use crate::Integrator;
+    use cgmath::{vec3, InnerSpace};
+
+    #[test]
+    fn hermite_follows_circular_orbit_between_samples() {
+        let mut start_conditions = start_conditions(
+            vec![
+                body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+                body(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 0.0),
+            ],
+            Integrator::RungeKutta4,
+        );
+        start_conditions.dt = 0.2;
+        let mut universe = Universe::new(&start_conditions);
+
+        let time: Float = 0.5;
+        let expected = vec3(time.cos(), time.sin(), 0.0);
+        let mut error = |interpolation| {
+            universe.interpolation = interpolation;
+            (universe.body_at_time(1, time).unwrap().pos - expected).magnitude()
+        };
+        let nearest = error(Interpolation::Nearest);
+        let linear = error(Interpolation::Linear);
+        let hermite = error(Interpolation::Hermite);
+        assert!(hermite < 1e-3, "{hermite}");
+        assert!(hermite < linear && linear < nearest);
+
+        let velocity = universe.body_at_time(1, time).unwrap().vel;
+        assert!((velocity - vec3(-time.sin(), time.cos(), 0.0)).magnitude() < 1e-2);
+    }
+
+    #[test]
+    fn interpolation_hits_stored_samples() {
+        let universe = Universe::new(&start_conditions(
+            vec![
+                body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+                body(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 0.0),
+            ],
+            Integrator::Leapfrog,
+        ));
+        for (i, step) in universe.bodies_path.steps().enumerate().step_by(7) {
+            let interpolated = universe.bodies_at_time(universe.step_time(i));
+            assert!((interpolated[1].pos - step.pos[1]).magnitude() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn light_cone_matches_bodies_at_time() {
+        let mut start_conditions = start_conditions(
+            vec![
+                body(vec3(-1.0, 0.0, 0.0), vec3(2.0, 0.0, 0.0), 1.0),
+                body(vec3(1.0, 0.0, 0.0), vec3(-2.0, 0.0, 0.0), 1.0),
+                body(vec3(0.0, 3.0, 0.0), vec3(0.0, 0.0, 1.0), 0.0),
+            ],
+            Integrator::Leapfrog,
+        );
+        start_conditions.softening = 0.05;
+        start_conditions.collisions = crate::CollisionMode::Merge;
+        let mut universe = Universe::new(&start_conditions);
+        assert!(!universe.merge_events.is_empty());
+
+        for interpolation in [Interpolation::Nearest, Interpolation::Hermite] {
+            universe.interpolation = interpolation;
+            // Around the merger, at the end and between stored steps.
+            for time in [0.6, universe.animation_length, 1.237] {
+                universe.time = time;
+                let cone = universe.light_cone();
+                assert_eq!(cone.len(), universe.light_iter_count());
+                for (i, step) in cone.steps().enumerate() {
+                    let bodies = universe.bodies_at_time(time - (i + 1) as Float * universe.dt);
+                    assert_eq!(step.pos.len(), bodies.len());
+                    for (b, body) in bodies.iter().enumerate() {
+                        assert_eq!(step.pos[b], body.pos);
+                        assert_eq!(
+                            (step.radius[b], step.mass[b], step.color[b].r),
+                            (body.radius, body.mass, body.color.r)
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
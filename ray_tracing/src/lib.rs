@@ -1,5 +1,58 @@
-use cgmath::{vec3, InnerSpace, MetricSpace, Vector3};
+/// Float type of the physics and photon tracing, `f64` with the `f64` feature.
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+/// `value` narrowed for things that are always single precision, like colors.
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn to_f32(value: Float) -> f32 {
+    value as f32
+}
+
+mod barnes_hut;
+mod camera;
+mod collisions;
+mod diagnostics;
+mod export;
+mod forces;
+mod generators;
+mod history;
+mod interpolation;
+mod motion;
+mod orbits;
+mod physics;
+mod sky;
+mod snapshot;
+#[cfg(test)]
+mod test_util;
+
+pub use barnes_hut::Octree;
+pub use camera::{
+    Camera, CameraInterpolation, CameraKeyframe, CubeFace, Projection, Stereo, StereoLayout,
+};
+pub use collisions::{CollisionMode, MergeEvent};
+pub use diagnostics::{write_diagnostics_csv, Diagnostics, Drift};
+pub use export::{write_trajectories_csv, write_trajectories_jsonl, TrajectoryPoint};
+pub use forces::ExternalForce;
+pub use generators::{ColorGradient, Generator, MassDistribution, Shape};
+pub use history::{BodyHistory, Step};
+pub use interpolation::{Interpolation, LightCone, LightStep};
+pub use motion::{Keyframe, Motion};
+pub use orbits::{BodyRef, OrbitalElements};
+pub use physics::{
+    acceleration, accelerations, post_newtonian_correction, GravitySolver, Integrator,
+    SimulationError, Stepper,
+};
+pub use sky::{Sky, SkyMap};
+pub use snapshot::{
+    read_universe, read_universe_from_file, write_universe, write_universe_to_file,
+    SNAPSHOT_VERSION,
+};
+
+use cgmath::{vec3, InnerSpace, MetricSpace, Vector3, Zero};
 use chrono::{Local, TimeDelta};
+use collisions::resolve_collisions;
 use rayon::prelude::*;
 use simple_video::*;
 use std::{
@@ -9,177 +62,423 @@ use std::{
 };
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "BodyConfig")]
 pub struct Body {
-    pub pos: Vector3<f32>,
-    pub vel: Vector3<f32>,
-    pub radius: f32,
+    /// Stable identity that follows the body through the whole simulation.
+    /// Bodies without one get a fresh id when the `Universe` is built.
+    pub id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// May be left out for bodies with an `orbit`.
+    pub pos: Vector3<Float>,
+    /// May be left out for bodies with an `orbit`.
+    pub vel: Vector3<Float>,
+    pub radius: Float,
     pub color: ColorF32,
-    pub mass: f32,
+    pub mass: Float,
+    /// Plummer softening length for pairs involving this body, overriding
+    /// `StartConditions::softening`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub softening: Option<Float>,
+    #[serde(skip_serializing_if = "Motion::is_dynamic")]
+    pub motion: Motion,
+    /// Initial state as an orbit around another body, which replaces `pos`
+    /// and `vel` when the `Universe` is built.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub orbit: Option<OrbitalElements>,
+}
+
+/// `Body` as it is written down, where only bodies with an `orbit` may leave
+/// out their `pos` and `vel`.
+#[derive(serde::Deserialize)]
+struct BodyConfig {
+    #[serde(default = "Body::unassigned_id")]
+    id: u32,
+    #[serde(default)]
+    name: Option<String>,
+    pos: Option<Vector3<Float>>,
+    vel: Option<Vector3<Float>>,
+    radius: Float,
+    color: ColorF32,
+    mass: Float,
+    #[serde(default)]
+    softening: Option<Float>,
+    #[serde(default)]
+    motion: Motion,
+    #[serde(default)]
+    orbit: Option<OrbitalElements>,
+}
+
+impl TryFrom<BodyConfig> for Body {
+    type Error = String;
+
+    fn try_from(config: BodyConfig) -> Result<Body, String> {
+        let (pos, vel) = match (config.pos, config.vel, &config.orbit) {
+            (Some(pos), Some(vel), _) => (pos, vel),
+            (pos, vel, Some(_)) => (
+                pos.unwrap_or_else(Vector3::zero),
+                vel.unwrap_or_else(Vector3::zero),
+            ),
+            (None, _, None) => {
+                return Err("missing field `pos` for a body without an `orbit`".into())
+            }
+            (_, None, None) => {
+                return Err("missing field `vel` for a body without an `orbit`".into())
+            }
+        };
+        Ok(Body {
+            id: config.id,
+            name: config.name,
+            pos,
+            vel,
+            radius: config.radius,
+            color: config.color,
+            mass: config.mass,
+            softening: config.softening,
+            motion: config.motion,
+            orbit: config.orbit,
+        })
+    }
 }
+
+impl Body {
+    pub const UNASSIGNED_ID: u32 = u32::MAX;
+
+    fn unassigned_id() -> u32 {
+        Self::UNASSIGNED_ID
+    }
+
+    /// Human readable reference to the body for messages.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{name:?} (id {})", self.id),
+            None => format!("id {}", self.id),
+        }
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct Universe {
-    pub time: f32,
-    pub animation_length: f32,
-    pub bodies_path: Vec<Vec<Body>>,
-    pub max_distance: f32,
-    pub light_speed: f32,
-    pub gravity_strength: f32,
-    pub dt: f32,
+    pub time: Float,
+    pub animation_length: Float,
+    pub bodies_path: BodyHistory,
+    pub max_distance: Float,
+    pub light_speed: Float,
+    pub gravity_strength: Float,
+    pub dt: Float,
+    #[serde(default)]
+    pub softening: Float,
+    #[serde(default)]
+    pub merge_events: Vec<MergeEvent>,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default)]
+    pub external_forces: Vec<ExternalForce>,
 }
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct StartConditions {
     pub width: usize,
     pub height: usize,
     pub fps: usize,
-    pub time: f32,
-    pub animation_length: f32,
+    pub time: Float,
+    pub animation_length: Float,
     pub bodies: Vec<Body>,
-    pub max_distance: f32,
-    pub light_speed: f32,
-    pub gravity_strength: f32,
-    pub dt: f32,
+    /// Groups of bodies added after `bodies`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generators: Vec<Generator>,
+    pub max_distance: Float,
+    pub light_speed: Float,
+    pub gravity_strength: Float,
+    pub dt: Float,
+    #[serde(default)]
+    pub integrator: Integrator,
+    #[serde(default)]
+    pub solver: GravitySolver,
+    /// Plummer softening length, so that forces stay finite as bodies meet.
+    #[serde(default)]
+    pub softening: Float,
+    /// Add first post-Newtonian (Einstein-Infeld-Hoffmann) corrections to the
+    /// gravity between bodies, with `light_speed` as the speed of light.
+    #[serde(default)]
+    pub post_newtonian: bool,
+    /// Applied to every body on top of their gravity, and to light as well.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub external_forces: Vec<ExternalForce>,
+    /// Only applied from the start of the animation; bodies in the light
+    /// history pass through each other.
+    #[serde(default)]
+    pub collisions: CollisionMode,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// Spread the force evaluation over all cores. Results are bit-identical
+    /// to a serial run.
+    #[serde(default)]
+    pub parallel: bool,
+    #[serde(default)]
+    pub camera: Camera,
+    /// Background for the light that escapes, instead of a plain grey.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sky: Option<Sky>,
 }
 
-impl Universe {
-    pub fn new(start_conditions: &StartConditions) -> Universe {
-        let mut bodies_path: Vec<Vec<Body>> = vec![start_conditions.bodies.clone()];
-
-        let mut current_bodies = start_conditions.bodies.clone();
-        for body in &mut current_bodies {
-            body.vel = -body.vel
+impl StartConditions {
+    /// `bodies` followed by the generated ones, with every unassigned id
+    /// replaced by a fresh one, orbital elements turned into positions and
+    /// velocities, and scripted bodies put on their path.
+    pub fn initial_bodies(&self) -> Result<Vec<Body>, SimulationError> {
+        let mut bodies = self.bodies.clone();
+        let mut seen = std::collections::HashSet::new();
+        for body in &bodies {
+            if body.id != Body::UNASSIGNED_ID && !seen.insert(body.id) {
+                return Err(SimulationError::DuplicateId(body.id));
+            }
         }
-        for _ in
-            0..(start_conditions.max_distance / start_conditions.dt / start_conditions.light_speed)
-                .ceil() as usize
-        {
-            for a in 0..current_bodies.len() {
-                for b in 0..current_bodies.len() {
-                    if a != b {
-                        if current_bodies[b].mass != 0.0 {
-                            let vel = (current_bodies[b].pos - current_bodies[a].pos).normalize()
-                                * (start_conditions.gravity_strength * current_bodies[b].mass
-                                    / (current_bodies[b].pos - current_bodies[a].pos).magnitude2());
-                            current_bodies[a].vel += vel * start_conditions.dt;
-                        }
+        let mut next_id = 0;
+        let mut assign_ids = |bodies: &mut [Body]| {
+            for body in bodies {
+                if body.id == Body::UNASSIGNED_ID {
+                    while seen.contains(&next_id) {
+                        next_id += 1;
                     }
+                    body.id = next_id;
+                    seen.insert(next_id);
                 }
-                let vel = current_bodies[a].vel * start_conditions.dt;
-                current_bodies[a].pos += vel;
             }
-            let mut new_vec: Vec<Vec<Body>> = vec![current_bodies.clone()];
-            new_vec.append(&mut bodies_path.clone());
-            bodies_path = new_vec.clone();
-        }
+        };
+        assign_ids(&mut bodies);
+        orbits::place_orbiting_bodies(&mut bodies, self.gravity_strength)?;
+        let given = bodies.len();
+        let generated =
+            generators::generate_bodies(&self.generators, &bodies, self.gravity_strength)?;
+        bodies.extend(generated);
+        assign_ids(&mut bodies[given..]);
+        let before = bodies.clone();
+        motion::constrain(&mut bodies, &before, 0.0, 0.0);
+        Ok(bodies)
+    }
+}
 
-        let mut current_bodies = start_conditions.bodies.clone();
-
-        for _ in 0..(start_conditions.animation_length / start_conditions.dt) as usize {
-            for a in 0..current_bodies.len() {
-                for b in 0..current_bodies.len() {
-                    if a != b {
-                        if current_bodies[b].mass != 0.0 {
-                            let vel = (current_bodies[b].pos - current_bodies[a].pos).normalize()
-                                * (start_conditions.gravity_strength * current_bodies[b].mass
-                                    / (current_bodies[b].pos - current_bodies[a].pos).magnitude2());
-                            current_bodies[a].vel += vel * start_conditions.dt;
-                        }
-                    }
-                }
-                let vel = current_bodies[a].vel * start_conditions.dt;
-                current_bodies[a].pos += vel;
+impl Universe {
+    /// Like `try_new`, but panics if the simulation breaks down.
+    pub fn new(start_conditions: &StartConditions) -> Universe {
+        Self::try_new(start_conditions).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    pub fn try_new(start_conditions: &StartConditions) -> Result<Universe, SimulationError> {
+        let initial_bodies = start_conditions.initial_bodies()?;
+        let light_steps =
+            (start_conditions.max_distance / start_conditions.dt / start_conditions.light_speed)
+                .ceil() as usize;
+        let animation_steps = (start_conditions.animation_length / start_conditions.dt) as usize;
+        let mut bodies_path =
+            BodyHistory::with_capacity(light_steps + 1 + animation_steps, initial_bodies.len());
+
+        // The light history is recorded backwards from the start and reversed afterwards.
+        bodies_path.push(&initial_bodies);
+        let mut current_bodies = initial_bodies.clone();
+        let mut stepper = Stepper::new(start_conditions, -start_conditions.dt);
+        for step in 0..light_steps {
+            stepper.step(&mut current_bodies);
+            SimulationError::check(
+                &current_bodies,
+                bodies_path.last().unwrap(),
+                step,
+                -((step + 1) as Float) * start_conditions.dt,
+            )?;
+            bodies_path.push(&current_bodies);
+        }
+        bodies_path.reverse();
+
+        let mut current_bodies = initial_bodies;
+        let mut stepper = Stepper::new(start_conditions, start_conditions.dt);
+        let mut merge_events = vec![];
+        for step in 0..animation_steps {
+            let time = (step + 1) as Float * start_conditions.dt;
+            stepper.step(&mut current_bodies);
+            SimulationError::check(&current_bodies, bodies_path.last().unwrap(), step, time)?;
+            if resolve_collisions(
+                &mut current_bodies,
+                start_conditions.collisions,
+                time,
+                &mut merge_events,
+            ) {
+                stepper.reset();
             }
-            bodies_path.append(&mut vec![current_bodies.clone()]);
+            bodies_path.push(&current_bodies);
         }
 
-        return Universe {
+        Ok(Universe {
             time: start_conditions.time,
             animation_length: start_conditions.animation_length,
-            bodies_path: bodies_path,
+            bodies_path,
             max_distance: start_conditions.max_distance,
             light_speed: start_conditions.light_speed,
             gravity_strength: start_conditions.gravity_strength,
             dt: start_conditions.dt,
-        };
+            softening: start_conditions.softening,
+            merge_events,
+            interpolation: start_conditions.interpolation,
+            external_forces: start_conditions.external_forces.clone(),
+        })
     }
 
     pub fn light_iter_count(&self) -> usize {
         (self.max_distance / self.dt / self.light_speed).ceil() as usize
     }
-    pub fn light_simulation_length(&self) -> f32 {
-        self.light_iter_count() as f32 * self.dt
+    pub fn light_simulation_length(&self) -> Float {
+        self.light_iter_count() as Float * self.dt
     }
-    pub fn time_percent(&self, time: f32) -> f32 {
+    pub fn time_percent(&self, time: Float) -> Float {
         (time + self.light_simulation_length())
             / (self.animation_length + self.light_simulation_length())
     }
-    pub fn get_bodies_at_time_percent(&self, time: f32) -> &Vec<Body> {
-        &self.bodies_path
-            [((self.bodies_path.len() as f32 * time) as usize).clamp(0, self.bodies_path.len() - 1)]
+    /// Id of the first body called `name`.
+    pub fn find_body(&self, name: &str) -> Option<u32> {
+        self.bodies_path.find_name(name)
+    }
+
+    /// Every stored state of the body with `id` along with its time, skipping
+    /// steps where it doesn't exist (yet or anymore).
+    pub fn trajectory(&self, id: u32) -> Vec<(Float, Body)> {
+        self.bodies_path
+            .steps()
+            .enumerate()
+            .filter_map(|(i, step)| {
+                let index = step.find(id)?;
+                Some((self.step_time(i), step.body(index)))
+            })
+            .collect()
+    }
+
+    pub fn get_bodies_at_time_percent(&self, time: Float) -> Vec<Body> {
+        self.bodies_path
+            .step(
+                ((self.bodies_path.len() as Float * time) as usize)
+                    .clamp(0, self.bodies_path.len() - 1),
+            )
+            .bodies()
     }
 }
 
-fn trace_ray(x: f32, y: f32, aspect: f32, universe: &Universe) -> ColorF32 {
-    let mut photon_pos = vec3(0.0, 0.0, 0.0);
-    let mut photon_dir =
-        vec3((x * 2.0 - 1.0) * aspect, y * 2.0 - 1.0, 1.0).normalize_to(universe.light_speed);
+/// Where a photon followed back from the camera ends up.
+#[derive(Debug)]
+enum Photon {
+    /// It came from a body of this color.
+    Absorbed(ColorF32),
+    /// It came from beyond everything, from this direction.
+    Escaped(Vector3<Float>),
+}
 
-    let mut elapsed = 0.0;
-    for i in 0..universe.light_iter_count() {
-        elapsed -= universe.dt;
-        let time = universe.time + elapsed;
+fn trace_ray(
+    x: Float,
+    y: Float,
+    aspect: Float,
+    camera: &Camera,
+    sky: Option<&SkyMap>,
+    universe: &Universe,
+    light_cone: &LightCone,
+) -> ColorF32 {
+    let Some((photon_pos, photon_dir)) = camera.primary_ray(x, y, aspect) else {
+        return ColorF32 {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+        };
+    };
+    let photon_dir = match trace_photon(photon_pos, photon_dir, universe, light_cone) {
+        Photon::Absorbed(color) => return color,
+        Photon::Escaped(photon_dir) => photon_dir,
+    };
+
+    if let Some(sky) = sky {
+        return sky.sample(photon_dir);
+    }
+    return ColorF32 {
+        r: 0.1,
+        g: 0.1,
+        b: 0.1,
+    };
+}
 
+fn trace_photon(
+    mut photon_pos: Vector3<Float>,
+    photon_dir: Vector3<Float>,
+    universe: &Universe,
+    light_cone: &LightCone,
+) -> Photon {
+    let mut photon_dir = photon_dir.normalize_to(universe.light_speed);
+    // Fields that reach everywhere keep bending light after it has left the bodies behind.
+    let bent_everywhere = universe
+        .external_forces
+        .iter()
+        .any(ExternalForce::bends_light);
+
+    for (i, bodies) in light_cone.steps().enumerate() {
         let iterations_left = universe.light_iter_count() - i;
-        let max_distance = iterations_left as f32 * universe.light_speed * universe.dt;
+        let max_distance = iterations_left as Float * universe.light_speed * universe.dt;
 
         let mut close_to_body = false;
-        for body in universe.get_bodies_at_time_percent(universe.time_percent(time)) {
-            let dist = photon_pos.distance(body.pos);
+        for (((&pos, &radius), &color), &mass) in bodies
+            .pos
+            .iter()
+            .zip(bodies.radius)
+            .zip(bodies.color)
+            .zip(bodies.mass)
+        {
+            let dist = photon_pos.distance(pos);
             if dist < max_distance {
                 close_to_body = true;
             }
-            if dist * dist < body.radius * body.radius {
-                return body.color;
+            if dist * dist < radius * radius {
+                return Photon::Absorbed(color);
             }
 
-            if body.mass != 0.0 {
-                let a = (4.0 * universe.gravity_strength * body.mass)
-                    / ((universe.light_speed * universe.light_speed)
-                        * dist);
-                let tug = a * (body.pos - photon_pos).normalize();
+            if mass != 0.0 {
+                let tug = forces::light_tug(
+                    pos - photon_pos,
+                    mass,
+                    universe.gravity_strength,
+                    universe.light_speed,
+                );
                 photon_dir += tug * universe.dt;
                 photon_dir = photon_dir.normalize_to(universe.light_speed);
             }
         }
-        if close_to_body == false {
-            break;
+        for force in &universe.external_forces {
+            photon_dir +=
+                force.light_tug(photon_pos, universe.gravity_strength, universe.light_speed)
+                    * universe.dt;
         }
         photon_dir = photon_dir.normalize_to(universe.light_speed);
+        if !close_to_body && !bent_everywhere {
+            break;
+        }
 
         photon_pos += photon_dir * universe.light_speed * universe.dt;
     }
-
-    return ColorF32 {
-        r: 0.1,
-        g: 0.1,
-        b: 0.1,
-    };
+    Photon::Escaped(photon_dir)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn trace_rays(
     pixels: &mut [ColorF32],
     width: usize,
     height: usize,
     universe: &Universe,
+    camera: &Camera,
+    sky: Option<&SkyMap>,
     fps: u8,
     i: usize,
     start: SystemTime,
 ) {
     let pixel_count = pixels.len();
     assert_eq!(pixel_count, width * height);
-    let aspect = width as f32 / height as f32;
+    let aspect = width as Float / height as Float;
 
     let start_frame = Instant::now();
+    let light_cone = universe.light_cone();
     let completed_pixels = AtomicUsize::new(0);
+    let frame_count = to_f32(universe.animation_length) * fps as f32;
     std::thread::scope(|s| {
         s.spawn(|| {
             pixels.par_iter_mut().enumerate().for_each(|(i, color)| {
@@ -195,12 +494,13 @@ pub fn trace_rays(
                 for y_offset in 0..samples_resolution {
                     for x_offset in 0..samples_resolution {
                         let (x, y) = (
-                            (x as f32 + (x_offset as f32 + 0.5) / samples_resolution as f32)
-                                / width as f32,
-                            (y as f32 + (y_offset as f32 + 0.5) / samples_resolution as f32)
-                                / height as f32,
+                            (x as Float + (x_offset as Float + 0.5) / samples_resolution as Float)
+                                / width as Float,
+                            (y as Float + (y_offset as Float + 0.5) / samples_resolution as Float)
+                                / height as Float,
                         );
-                        samples_color += trace_ray(x, y, aspect, &universe);
+                        samples_color +=
+                            trace_ray(x, y, aspect, camera, sky, universe, &light_cone);
                     }
                 }
                 *color = ColorF32 {
@@ -219,8 +519,7 @@ pub fn trace_rays(
 
             let time = start.elapsed().unwrap();
             let eta = time.as_secs_f32() as f32
-                / ((i as f32 + (progress as f32 / pixel_count as f32))
-                    / (universe.animation_length * fps as f32))
+                / ((i as f32 + (progress as f32 / pixel_count as f32)) / frame_count)
                 - time.as_secs_f32();
             let time_spent_rendering = time.as_secs_f32();
             let finish_time = if i == 0 {
@@ -235,8 +534,8 @@ pub fn trace_rays(
                 (progress as f32 / pixel_count as f32) * 100.0,
                 total_time.as_secs_f32(),
                 i,
-                universe.animation_length * fps as f32,
-                (i as f32 + (progress as f32 / pixel_count as f32)) / (universe.animation_length * fps as f32) * 100.0,
+                frame_count,
+                (i as f32 + (progress as f32 / pixel_count as f32)) / frame_count * 100.0,
                 (eta / 60.0 /60.0).floor(),
                 (eta / 60.0 % 60.0).floor(),
                 (eta % 60.0).floor(),
@@ -255,23 +554,22 @@ pub fn trace_rays(
     assert_eq!(completed_pixels.into_inner(), pixel_count);
 }
 
-#[allow(dead_code)]
 trait Lerp {
-    fn lerp(a: Self, b: Self, t: f32) -> Self;
+    fn lerp(a: Self, b: Self, t: Float) -> Self;
 }
 
-impl Lerp for f32 {
-    fn lerp(a: Self, b: Self, t: f32) -> Self {
+impl Lerp for Float {
+    fn lerp(a: Self, b: Self, t: Float) -> Self {
         a + (b - a) * t
     }
 }
 
-impl Lerp for Vector3<f32> {
-    fn lerp(a: Self, b: Self, t: f32) -> Self {
+impl Lerp for Vector3<Float> {
+    fn lerp(a: Self, b: Self, t: Float) -> Self {
         vec3(
-            f32::lerp(a.x, b.x, t),
-            f32::lerp(a.y, b.y, t),
-            f32::lerp(a.z, b.z, t),
+            Float::lerp(a.x, b.x, t),
+            Float::lerp(a.y, b.y, t),
+            Float::lerp(a.z, b.z, t),
         )
     }
 }
@@ -375,3 +673,131 @@ impl Lerp for Vector3<f32> {
 //         b: 0.1,
 //     };
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+
+    #[test]
+    fn scene_files_load_in_either_precision() {
+        let start_conditions: StartConditions =
+            serde_json::from_str(include_str!("../../Gravity Render.render")).unwrap();
+        assert_eq!(start_conditions.bodies.len(), 3);
+        assert_eq!(start_conditions.bodies[1].vel, vec3(0.0, 0.0, 1.0));
+        assert_eq!(start_conditions.dt, 0.005);
+    }
+
+    #[test]
+    fn only_orbiting_bodies_may_leave_out_their_state() {
+        let color = r#""color": {"r": 1, "g": 1, "b": 1}"#;
+        let moon: Body = serde_json::from_str(&format!(
+            r#"{{"radius": 0.1, "mass": 0, {color}, "orbit": {{"parent": 0, "semi_major_axis": 2}}}}"#
+        ))
+        .unwrap();
+        assert_eq!(moon.pos, Vector3::zero());
+
+        let misspelled =
+            format!(r#"{{"radius": 0.1, "mass": 1, {color}, "pso": [1, 0, 0], "vel": [0, 0, 0]}}"#);
+        let error = serde_json::from_str::<Body>(&misspelled).unwrap_err();
+        assert!(error.to_string().contains("`pos`"), "{error}");
+        let no_vel = format!(r#"{{"radius": 0.1, "mass": 1, {color}, "pos": [1, 0, 0]}}"#);
+        let error = serde_json::from_str::<Body>(&no_vel).unwrap_err();
+        assert!(error.to_string().contains("`vel`"), "{error}");
+    }
+
+    #[test]
+    fn missing_ids_are_assigned_around_given_ones() {
+        let mut bodies = vec![body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0); 3];
+        bodies[1].id = 0;
+        bodies[2].name = Some("moon".to_string());
+        let mut start_conditions = start_conditions(bodies, Integrator::Leapfrog);
+        let ids: Vec<u32> = start_conditions
+            .initial_bodies()
+            .unwrap()
+            .iter()
+            .map(|body| body.id)
+            .collect();
+        assert_eq!(ids, [1, 0, 2]);
+
+        start_conditions.bodies[2].id = 0;
+        assert_eq!(
+            start_conditions.initial_bodies().unwrap_err(),
+            SimulationError::DuplicateId(0)
+        );
+    }
+
+    #[test]
+    fn bodies_can_be_looked_up_by_name() {
+        let mut bodies = vec![
+            body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+            body(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 0.0),
+        ];
+        bodies[1].name = Some("moon".to_string());
+        let universe = Universe::new(&start_conditions(bodies, Integrator::Leapfrog));
+
+        let moon = universe.find_body("moon").unwrap();
+        assert_eq!(moon, 1);
+        assert_eq!(universe.trajectory(moon).len(), universe.bodies_path.len());
+        assert_eq!(universe.bodies_path.name(moon), Some("moon"));
+        let at_start = universe.body_at_time(moon, 0.0).unwrap();
+        assert_eq!(at_start.pos, vec3(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn external_forces_bend_light_away_from_bodies() {
+        let ahead = vec3(0.0, 0.0, 1.0);
+        let escaped = |universe: &Universe| match trace_photon(
+            Vector3::zero(),
+            ahead,
+            universe,
+            &universe.light_cone(),
+        ) {
+            Photon::Escaped(direction) => direction,
+            Photon::Absorbed(_) => panic!("there is no body to absorb the photon"),
+        };
+        let mut start_conditions = start_conditions(vec![], Integrator::Leapfrog);
+        start_conditions.max_distance = 10.0;
+        assert_eq!(escaped(&Universe::new(&start_conditions)), ahead);
+
+        start_conditions.external_forces = vec![ExternalForce::PointMass {
+            pos: vec3(1.0, 0.0, 5.0),
+            mass: 0.1,
+        }];
+        let direction = escaped(&Universe::new(&start_conditions));
+        assert!(direction.x > 0.01, "{direction:?}");
+        assert!(direction.y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn default_camera_sees_the_sky_upright() {
+        let pixels: Vec<ColorF32> = (0..8)
+            .map(|i| ColorF32 {
+                r: (i % 4) as f32 / 4.0,
+                g: (i / 4) as f32,
+                b: 0.5,
+            })
+            .collect();
+        let sky = SkyMap::new(4, 2, pixels.clone());
+        let universe = Universe::new(&start_conditions(vec![], Integrator::Leapfrog));
+        let light_cone = universe.light_cone();
+        let trace = |camera: &Camera, x, y, aspect| {
+            trace_ray(x, y, aspect, camera, Some(&sky), &universe, &light_cone)
+        };
+
+        // The top row of the image is at the top of the frame.
+        let camera = Camera::default();
+        assert!(trace(&camera, 0.5, 0.02, 1.0).g < 0.1);
+        assert!(trace(&camera, 0.5, 0.98, 1.0).g > 0.9);
+
+        let panorama = Camera {
+            projection: Projection::Equirectangular,
+            ..Camera::default()
+        };
+        for (i, pixel) in pixels.iter().enumerate() {
+            let (x, y) = ((i % 4) as Float + 0.5, (i / 4) as Float + 0.5);
+            let seen = trace(&panorama, x / 4.0, y / 2.0, 2.0);
+            assert!((seen.r - pixel.r).abs() < 1e-4 && (seen.g - pixel.g).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::{Body, Float};
+use cgmath::{InnerSpace, Vector3, Zero};
+
+/// Position a `Motion::Keyframes` body passes through at `time`.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Keyframe {
+    pub time: Float,
+    pub pos: Vector3<Float>,
+}
+
+/// How a body moves. Bodies that don't follow gravity still attract the others
+/// and bend light like any other body.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Motion {
+    /// Moved by gravity.
+    #[default]
+    Dynamic,
+    /// Stays where it starts.
+    Fixed,
+    /// Circles around `axis` through `center` once every `period`, starting
+    /// from its initial position. Negative periods go the other way round.
+    Orbit {
+        center: Vector3<Float>,
+        axis: Vector3<Float>,
+        period: Float,
+    },
+    /// Moves in straight lines between keyframes ordered by time, with time 0
+    /// being the start of the simulation. Rests at the first and last one
+    /// outside of their range.
+    Keyframes(Vec<Keyframe>),
+}
+
+impl Motion {
+    pub fn is_dynamic(&self) -> bool {
+        *self == Motion::Dynamic
+    }
+
+    /// Angular velocity of an orbit.
+    fn spin(axis: Vector3<Float>, period: Float) -> Vector3<Float> {
+        axis.normalize() * (std::f64::consts::TAU as Float / period)
+    }
+
+    /// Position and velocity of a scripted body that was in state `before` at
+    /// `time`, after moving on for `dt`. `None` for dynamic bodies.
+    pub fn moved(
+        &self,
+        before: &Body,
+        time: Float,
+        dt: Float,
+    ) -> Option<(Vector3<Float>, Vector3<Float>)> {
+        match self {
+            Motion::Dynamic => None,
+            Motion::Fixed => Some((before.pos, Vector3::zero())),
+            Motion::Orbit {
+                center,
+                axis,
+                period,
+            } => {
+                let spin = Self::spin(*axis, *period);
+                let axis = axis.normalize();
+                let (sin, cos) = (spin.magnitude() * dt).sin_cos();
+                let offset = before.pos - center;
+                // Rodrigues' rotation of the offset around the axis.
+                let offset = offset * cos
+                    + axis.cross(offset) * sin
+                    + axis * (axis.dot(offset) * (1.0 - cos));
+                Some((center + offset, spin.cross(offset)))
+            }
+            Motion::Keyframes(keyframes) => {
+                let time = time + dt;
+                let next = keyframes.iter().position(|keyframe| keyframe.time > time);
+                Some(match next {
+                    None => (keyframes.last()?.pos, Vector3::zero()),
+                    Some(0) => (keyframes[0].pos, Vector3::zero()),
+                    Some(next) => {
+                        let (a, b) = (&keyframes[next - 1], &keyframes[next]);
+                        let vel = (b.pos - a.pos) / (b.time - a.time);
+                        (a.pos + vel * (time - a.time), vel)
+                    }
+                })
+            }
+        }
+    }
+
+    /// Acceleration of a scripted `body`, which replaces the gravitational one.
+    /// `None` for dynamic bodies.
+    pub fn acceleration(&self, body: &Body) -> Option<Vector3<Float>> {
+        match self {
+            Motion::Dynamic => None,
+            Motion::Fixed | Motion::Keyframes(_) => Some(Vector3::zero()),
+            Motion::Orbit {
+                center,
+                axis,
+                period,
+            } => {
+                let spin = Self::spin(*axis, *period);
+                Some(spin.cross(spin.cross(body.pos - center)))
+            }
+        }
+    }
+}
+
+/// Puts every scripted body of `bodies` back on its path, given their states
+/// `before` at `time` and `dt` earlier.
+pub(crate) fn constrain(bodies: &mut [Body], before: &[Body], time: Float, dt: Float) {
+    for (body, before) in bodies.iter_mut().zip(before) {
+        if let Some((pos, vel)) = body.motion.moved(before, time, dt) {
+            body.pos = pos;
+            body.vel = vel;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+    use crate::{Integrator, Universe};
+    use cgmath::{vec3, MetricSpace};
+
+    #[test]
+    fn fixed_body_stays_put_and_attracts() {
+        let mut start_conditions = start_conditions(
+            vec![
+                body(vec3(0.0, 0.0, 0.0), vec3(0.3, 0.0, 0.0), 1.0),
+                body(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 1.0),
+            ],
+            Integrator::Leapfrog,
+        );
+        start_conditions.bodies[0].motion = Motion::Fixed;
+        let universe = Universe::new(&start_conditions);
+        for step in universe.bodies_path.steps() {
+            assert_eq!(step.pos[0], vec3(0.0, 0.0, 0.0));
+            assert_eq!(step.vel[0], vec3(0.0, 0.0, 0.0));
+        }
+        let last = universe.bodies_path.last().unwrap();
+        assert!(last.pos[1].x < 1.0);
+        assert!((last.pos[1].magnitude() - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn scripted_paths_are_followed_through_the_whole_history() {
+        let mut start_conditions = start_conditions(
+            vec![
+                body(vec3(2.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+                body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+                body(vec3(0.0, 3.0, 0.0), vec3(0.0, 0.0, 0.0), 0.0),
+            ],
+            Integrator::RungeKutta4,
+        );
+        start_conditions.bodies[0].motion = Motion::Orbit {
+            center: vec3(0.0, 0.0, 0.0),
+            axis: vec3(0.0, 0.0, 2.0),
+            period: 4.0,
+        };
+        start_conditions.bodies[1].motion = Motion::Keyframes(vec![
+            Keyframe {
+                time: -0.5,
+                pos: vec3(0.0, 0.0, -1.0),
+            },
+            Keyframe {
+                time: 0.5,
+                pos: vec3(0.0, 0.0, 1.0),
+            },
+        ]);
+        let universe = Universe::new(&start_conditions);
+        for (i, step) in universe.bodies_path.steps().enumerate() {
+            let time = universe.step_time(i);
+            let angle = time * std::f64::consts::TAU as Float / 4.0;
+            let expected = vec3(angle.cos(), angle.sin(), 0.0) * 2.0;
+            assert!(step.pos[0].distance(expected) < 1e-4, "{time}");
+            let z = (time * 2.0).clamp(-1.0, 1.0);
+            assert!(step.pos[1].distance(vec3(0.0, 0.0, z)) < 1e-4, "{time}");
+        }
+    }
+}
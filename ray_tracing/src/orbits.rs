@@ -0,0 +1,191 @@
+use crate::{Body, Float, SimulationError};
+use cgmath::{vec3, Vector3};
+
+/// Reference to another body by id or by name.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum BodyRef {
+    Id(u32),
+    Name(String),
+}
+
+impl BodyRef {
+    pub(crate) fn find(&self, bodies: &[Body]) -> Option<usize> {
+        bodies.iter().position(|body| match self {
+            BodyRef::Id(id) => body.id == *id,
+            BodyRef::Name(name) => body.name.as_deref() == Some(name),
+        })
+    }
+}
+
+impl std::fmt::Display for BodyRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyRef::Id(id) => write!(f, "id {id}"),
+            BodyRef::Name(name) => write!(f, "{name:?}"),
+        }
+    }
+}
+
+/// Keplerian orbit around `parent`, as an alternative to giving a body's
+/// position and velocity directly.
+///
+/// Angles are in degrees. The reference plane is the xz plane, with +y
+/// pointing north and +x towards the vernal point, so prograde orbits with no
+/// inclination turn counterclockwise when seen from above. Hyperbolic orbits
+/// take a negative `semi_major_axis`.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct OrbitalElements {
+    pub parent: BodyRef,
+    pub semi_major_axis: Float,
+    #[serde(default)]
+    pub eccentricity: Float,
+    #[serde(default)]
+    pub inclination: Float,
+    #[serde(default)]
+    pub ascending_node: Float,
+    #[serde(default)]
+    pub argument_of_periapsis: Float,
+    #[serde(default)]
+    pub true_anomaly: Float,
+}
+
+impl OrbitalElements {
+    /// Position and velocity relative to the parent, where `mu` is the
+    /// gravity strength times the mass of the parent and the body together.
+    pub fn state(&self, mu: Float) -> (Vector3<Float>, Vector3<Float>) {
+        let e = self.eccentricity;
+        let p = self.semi_major_axis * (1.0 - e * e);
+        let (sin_nu, cos_nu) = self.true_anomaly.to_radians().sin_cos();
+        let r = p / (1.0 + e * cos_nu);
+        let speed = (mu / p).sqrt();
+        // In the orbital plane, with the periapsis along the first axis.
+        let pos = (r * cos_nu, r * sin_nu);
+        let vel = (-speed * sin_nu, speed * (e + cos_nu));
+
+        let (sin_w, cos_w) = self.argument_of_periapsis.to_radians().sin_cos();
+        let (sin_o, cos_o) = self.ascending_node.to_radians().sin_cos();
+        let (sin_i, cos_i) = self.inclination.to_radians().sin_cos();
+        let to_reference = |(x, y): (Float, Float)| {
+            let (x, y) = (x * cos_w - y * sin_w, x * sin_w + y * cos_w);
+            let (y, north) = (y * cos_i, y * sin_i);
+            let (x, y) = (x * cos_o - y * sin_o, x * sin_o + y * cos_o);
+            vec3(x, north, -y)
+        };
+        (to_reference(pos), to_reference(vel))
+    }
+}
+
+/// Replaces the position and velocity of every body with `orbit` by the state
+/// its elements describe, placing parents before their satellites.
+pub(crate) fn place_orbiting_bodies(
+    bodies: &mut [Body],
+    gravity_strength: Float,
+) -> Result<(), SimulationError> {
+    let mut placed: Vec<bool> = bodies.iter().map(|body| body.orbit.is_none()).collect();
+    loop {
+        let mut progress = false;
+        for index in 0..bodies.len() {
+            if placed[index] {
+                continue;
+            }
+            let orbit = bodies[index].orbit.clone().unwrap();
+            let Some(parent) = orbit.parent.find(bodies) else {
+                return Err(SimulationError::UnknownParent {
+                    body: bodies[index].label(),
+                    parent: orbit.parent.to_string(),
+                });
+            };
+            if !placed[parent] {
+                continue;
+            }
+            let mu = gravity_strength * (bodies[parent].mass + bodies[index].mass);
+            let (pos, vel) = orbit.state(mu);
+            bodies[index].pos = bodies[parent].pos + pos;
+            bodies[index].vel = bodies[parent].vel + vel;
+            placed[index] = true;
+            progress = true;
+        }
+        if !progress {
+            break;
+        }
+    }
+    match placed.iter().position(|&placed| !placed) {
+        Some(index) => Err(SimulationError::OrbitCycle(bodies[index].label())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+    use crate::Integrator;
+    use cgmath::{InnerSpace, MetricSpace};
+
+    fn elements(parent: BodyRef) -> OrbitalElements {
+        OrbitalElements {
+            parent,
+            semi_major_axis: 2.0,
+            eccentricity: 0.3,
+            inclination: 30.0,
+            ascending_node: 60.0,
+            argument_of_periapsis: 45.0,
+            true_anomaly: 120.0,
+        }
+    }
+
+    #[test]
+    fn state_matches_elements() {
+        let mu = 3.0;
+        let elements = elements(BodyRef::Id(0));
+        let (pos, vel) = elements.state(mu);
+
+        let energy = 0.5 * vel.magnitude2() - mu / pos.magnitude();
+        assert!((energy + mu / (2.0 * elements.semi_major_axis)).abs() < 1e-5);
+        let p = elements.semi_major_axis * (1.0 - elements.eccentricity.powi(2));
+        let momentum = pos.cross(vel);
+        assert!((momentum.magnitude() - (mu * p).sqrt()).abs() < 1e-5);
+
+        // The usual (sin i sin Ω, -sin i cos Ω, cos i), turned so that y points north.
+        let (i, node) = (30.0 as Float).to_radians().sin_cos();
+        let (sin_o, cos_o) = (60.0 as Float).to_radians().sin_cos();
+        let normal = vec3(i * sin_o, node, i * cos_o);
+        assert!(momentum.normalize().distance(normal) < 1e-5);
+    }
+
+    #[test]
+    fn satellites_are_placed_after_their_parents() {
+        let mut moon = body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 0.0);
+        moon.orbit = Some(OrbitalElements {
+            semi_major_axis: 0.5,
+            ..elements(BodyRef::Name("planet".to_string()))
+        });
+        let mut planet = body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 0.1);
+        planet.name = Some("planet".to_string());
+        planet.orbit = Some(elements(BodyRef::Id(2)));
+        let star = body(vec3(1.0, 2.0, 3.0), vec3(0.5, 0.0, 0.0), 1.0);
+
+        let mut start_conditions = start_conditions(vec![moon, planet, star], Integrator::Leapfrog);
+        start_conditions.gravity_strength = 2.0;
+        let bodies = start_conditions.initial_bodies().unwrap();
+        let (pos, vel) = elements(BodyRef::Id(2)).state(2.0 * 1.1);
+        assert_eq!(bodies[1].pos, vec3(1.0, 2.0, 3.0) + pos);
+        assert_eq!(bodies[1].vel, vec3(0.5, 0.0, 0.0) + vel);
+        assert!((bodies[0].pos.distance(bodies[1].pos) - 0.5 * 0.91 / 0.85).abs() < 1e-5);
+
+        start_conditions.bodies[1].orbit = Some(elements(BodyRef::Id(0)));
+        assert_eq!(
+            start_conditions.initial_bodies().unwrap_err(),
+            SimulationError::OrbitCycle("id 0".to_string())
+        );
+        start_conditions.bodies[1].name = None;
+        assert_eq!(
+            start_conditions.initial_bodies().unwrap_err(),
+            SimulationError::UnknownParent {
+                body: "id 0".to_string(),
+                parent: "\"planet\"".to_string()
+            }
+        );
+    }
+}
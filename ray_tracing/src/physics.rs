@@ -0,0 +1,1286 @@
+use crate::{barnes_hut::Octree, motion::constrain, Body, Float, StartConditions, Step};
+use cgmath::{InnerSpace, MetricSpace, Vector3, Zero};
+use rayon::prelude::*;
+
+/// Scheme used to advance the bodies by one `dt`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Integrator {
+    /// Semi-implicit (symplectic) Euler: kick with the current forces, then drift.
+    #[default]
+    Euler,
+    /// Kick-drift-kick leapfrog with half-step velocity kicks.
+    Leapfrog,
+    /// Velocity Verlet, algebraically the same as `Leapfrog` but written as a
+    /// second-order position update followed by an averaged velocity update.
+    VelocityVerlet,
+    /// Classic fourth-order Runge-Kutta.
+    RungeKutta4,
+    /// Adaptive Dormand-Prince 5(4). Internal steps are sized to keep the
+    /// per-step error below `tolerance` and are resampled onto the fixed `dt` grid.
+    DormandPrince { tolerance: Float },
+}
+
+/// How the body-body gravity is evaluated each step.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum GravitySolver {
+    /// Exact O(N²) sum over every pair of bodies.
+    #[default]
+    Direct,
+    /// Barnes-Hut octree, O(N log N). Cells that appear smaller than `theta`
+    /// radians from a body are replaced by their centre of mass; 0.0 is exact.
+    BarnesHut { theta: Float },
+}
+
+/// Squared Plummer softening length between two bodies, where `softening` is
+/// used for bodies without their own.
+pub(crate) fn pair_softening2(a: &Body, b: &Body, softening: Float) -> Float {
+    let a = a.softening.unwrap_or(softening);
+    let b = b.softening.unwrap_or(softening);
+    0.5 * (a * a + b * b)
+}
+
+/// Plummer-softened pull of `mass` at `offset` from the attracted body.
+pub(crate) fn pull(
+    offset: Vector3<Float>,
+    mass: Float,
+    gravity_strength: Float,
+    softening2: Float,
+) -> Vector3<Float> {
+    let distance2 = offset.magnitude2() + softening2;
+    offset * (gravity_strength * mass / (distance2 * distance2.sqrt()))
+}
+
+/// Gravitational acceleration of the body at index `a` from all others.
+pub fn acceleration(
+    bodies: &[Body],
+    a: usize,
+    gravity_strength: Float,
+    softening: Float,
+) -> Vector3<Float> {
+    let mut acceleration = Vector3::zero();
+    for (b, other) in bodies.iter().enumerate() {
+        if a == b || other.mass == 0.0 {
+            continue;
+        }
+        acceleration += pull(
+            other.pos - bodies[a].pos,
+            other.mass,
+            gravity_strength,
+            pair_softening2(&bodies[a], other, softening),
+        );
+    }
+    acceleration
+}
+
+/// Gravitational acceleration of every body, computed from one consistent snapshot.
+pub fn accelerations(
+    bodies: &[Body],
+    gravity_strength: Float,
+    softening: Float,
+) -> Vec<Vector3<Float>> {
+    (0..bodies.len())
+        .map(|a| acceleration(bodies, a, gravity_strength, softening))
+        .collect()
+}
+
+/// Massive bodies of one snapshot in flat arrays. Only they pull on others,
+/// so massless test particles are never visited as sources.
+struct Attractors {
+    index: Vec<usize>,
+    pos: Vec<Vector3<Float>>,
+    mass: Vec<Float>,
+    softening2: Vec<Float>,
+}
+
+impl Attractors {
+    fn new(bodies: &[Body], softening: Float) -> Attractors {
+        let mut attractors = Attractors {
+            index: vec![],
+            pos: vec![],
+            mass: vec![],
+            softening2: vec![],
+        };
+        for (index, body) in bodies.iter().enumerate() {
+            if body.mass != 0.0 {
+                let softening = body.softening.unwrap_or(softening);
+                attractors.index.push(index);
+                attractors.pos.push(body.pos);
+                attractors.mass.push(body.mass);
+                attractors.softening2.push(softening * softening);
+            }
+        }
+        attractors
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Acceleration of the `a`th attractor from all others. Sums in the same
+    /// order as `acceleration`, so the results are identical.
+    fn acceleration(&self, a: usize, gravity_strength: Float) -> Vector3<Float> {
+        let mut acceleration = Vector3::zero();
+        for b in 0..self.len() {
+            if a != b {
+                acceleration += pull(
+                    self.pos[b] - self.pos[a],
+                    self.mass[b],
+                    gravity_strength,
+                    0.5 * (self.softening2[a] + self.softening2[b]),
+                );
+            }
+        }
+        acceleration
+    }
+
+    /// Adds the pull of every attractor to the test particles at `pos`. Goes
+    /// attractor by attractor so that the inner loop runs over contiguous
+    /// particles, while each particle still sums in the same order as
+    /// `acceleration`.
+    fn pull_on_particles(
+        &self,
+        pos: &[Vector3<Float>],
+        softening2: &[Float],
+        accelerations: &mut [Vector3<Float>],
+        gravity_strength: Float,
+    ) {
+        for b in 0..self.len() {
+            for ((acceleration, &pos), &softening2) in
+                accelerations.iter_mut().zip(pos).zip(softening2)
+            {
+                *acceleration += pull(
+                    self.pos[b] - pos,
+                    self.mass[b],
+                    gravity_strength,
+                    0.5 * (softening2 + self.softening2[b]),
+                );
+            }
+        }
+    }
+}
+
+/// Massless, freely moving bodies of one snapshot in flat arrays. They feel
+/// the attractors but pull on nothing, so the fixed-step integrators advance
+/// them in their own pass once the attractors have moved.
+struct TestParticles {
+    index: Vec<usize>,
+    pos: Vec<Vector3<Float>>,
+    vel: Vec<Vector3<Float>>,
+    softening2: Vec<Float>,
+}
+
+impl TestParticles {
+    fn contains(body: &Body) -> bool {
+        body.mass == 0.0 && body.motion.is_dynamic()
+    }
+
+    fn new(bodies: &[Body], softening: Float) -> TestParticles {
+        let mut particles = TestParticles {
+            index: vec![],
+            pos: vec![],
+            vel: vec![],
+            softening2: vec![],
+        };
+        for (index, body) in bodies.iter().enumerate() {
+            if TestParticles::contains(body) {
+                let softening = body.softening.unwrap_or(softening);
+                particles.index.push(index);
+                particles.pos.push(body.pos);
+                particles.vel.push(body.vel);
+                particles.softening2.push(softening * softening);
+            }
+        }
+        particles
+    }
+
+    /// Writes the particles back into the `bodies` they were taken from.
+    fn store(&self, bodies: &mut [Body]) {
+        for ((&index, &pos), &vel) in self.index.iter().zip(&self.pos).zip(&self.vel) {
+            bodies[index].pos = pos;
+            bodies[index].vel = vel;
+        }
+    }
+}
+
+/// Newtonian potential `Σ G m / r` at the body at index `a` from all others.
+pub(crate) fn potential(
+    bodies: &[Body],
+    a: usize,
+    gravity_strength: Float,
+    softening: Float,
+) -> Float {
+    let mut potential = 0.0;
+    for (b, other) in bodies.iter().enumerate() {
+        if a == b || other.mass == 0.0 {
+            continue;
+        }
+        let distance2 =
+            bodies[a].pos.distance2(other.pos) + pair_softening2(&bodies[a], other, softening);
+        potential += gravity_strength * other.mass / distance2.sqrt();
+    }
+    potential
+}
+
+/// First post-Newtonian (Einstein-Infeld-Hoffmann) correction to the
+/// acceleration of the body at index `a`, given the Newtonian acceleration of
+/// and `potential` at every body. Bodies move in harmonic coordinates and the
+/// Newtonian accelerations stand in for the ones inside the correction terms.
+pub fn post_newtonian_correction(
+    bodies: &[Body],
+    newtonian: &[Vector3<Float>],
+    potentials: &[Float],
+    a: usize,
+    gravity_strength: Float,
+    light_speed: Float,
+    softening: Float,
+) -> Vector3<Float> {
+    let body = &bodies[a];
+    let mut correction = Vector3::zero();
+    for (b, other) in bodies.iter().enumerate() {
+        if a == b || other.mass == 0.0 {
+            continue;
+        }
+        let offset = other.pos - body.pos;
+        let distance2 = offset.magnitude2() + pair_softening2(body, other, softening);
+        let distance = distance2.sqrt();
+        let strength = gravity_strength * other.mass / (distance2 * distance);
+        let radial_vel = offset.dot(other.vel) / distance;
+
+        let factor = -4.0 * potentials[a] - potentials[b]
+            + body.vel.magnitude2()
+            + 2.0 * other.vel.magnitude2()
+            - 4.0 * body.vel.dot(other.vel)
+            - 1.5 * radial_vel * radial_vel
+            + 0.5 * offset.dot(newtonian[b]);
+        correction += offset * (strength * factor);
+        correction +=
+            (body.vel - other.vel) * (strength * -offset.dot(body.vel * 4.0 - other.vel * 3.0));
+        correction += newtonian[b] * (3.5 * gravity_strength * other.mass / distance);
+    }
+    correction / (light_speed * light_speed)
+}
+
+/// Index of the first body whose position or velocity is no longer finite.
+pub(crate) fn find_non_finite(bodies: &[Body]) -> Option<usize> {
+    bodies.iter().position(|body| {
+        !(body.pos.x.is_finite()
+            && body.pos.y.is_finite()
+            && body.pos.z.is_finite()
+            && body.vel.x.is_finite()
+            && body.vel.y.is_finite()
+            && body.vel.z.is_finite())
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimulationError {
+    /// A body's position or velocity became infinite or NaN, usually after a
+    /// close encounter with `closest` in the step before.
+    NonFinite {
+        step: usize,
+        time: Float,
+        body: String,
+        closest: Option<(String, Float)>,
+    },
+    /// Two bodies were given the same id.
+    DuplicateId(u32),
+    /// The parent that a body's orbital elements refer to doesn't exist.
+    UnknownParent { body: String, parent: String },
+    /// A body's orbit is, through its parents, relative to itself.
+    OrbitCycle(String),
+    /// The body that the generator at index `generator` is centred on doesn't exist.
+    UnknownCenter { generator: usize, center: String },
+    /// The shape of the generator at index `generator` can't be filled with bodies.
+    InvalidShape {
+        generator: usize,
+        problem: &'static str,
+    },
+    /// The body that the camera follows or looks at doesn't exist.
+    UnknownCameraTarget(String),
+    /// The camera is at the point it looks at, so it looks nowhere.
+    CameraAtTarget { time: Float },
+}
+
+impl std::fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimulationError::NonFinite {
+                step,
+                time,
+                body,
+                closest,
+            } => {
+                write!(
+                    f,
+                    "body {body} became non-finite at step {step} (time {time})"
+                )?;
+                if let Some((closest, distance)) = closest {
+                    write!(
+                        f,
+                        ", it was {distance} away from body {closest} the step before; \
+                         try a smaller dt or a softening length"
+                    )?;
+                }
+                Ok(())
+            }
+            SimulationError::DuplicateId(id) => write!(f, "more than one body has id {id}"),
+            SimulationError::UnknownParent { body, parent } => {
+                write!(f, "body {body} orbits {parent}, which doesn't exist")
+            }
+            SimulationError::OrbitCycle(body) => {
+                write!(
+                    f,
+                    "the orbit of body {body} circles back to itself through its parents"
+                )
+            }
+            SimulationError::UnknownCenter { generator, center } => {
+                write!(
+                    f,
+                    "generator {generator} is centred on {center}, which doesn't exist"
+                )
+            }
+            SimulationError::InvalidShape { generator, problem } => {
+                write!(f, "generator {generator} has an invalid shape: {problem}")
+            }
+            SimulationError::UnknownCameraTarget(body) => {
+                write!(f, "the camera is pointed at {body}, which doesn't exist")
+            }
+            SimulationError::CameraAtTarget { time } => {
+                write!(f, "the camera is at the point it looks at (time {time})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+impl SimulationError {
+    /// Error for the first non-finite body in `bodies`, if any, using the
+    /// `previous` snapshot to find the body it was closest to.
+    pub(crate) fn check(
+        bodies: &[Body],
+        previous: Step<'_>,
+        step: usize,
+        time: Float,
+    ) -> Result<(), SimulationError> {
+        let Some(body) = find_non_finite(bodies) else {
+            return Ok(());
+        };
+        let id = bodies[body].id;
+        let closest = previous.find(id).and_then(|before| {
+            (0..previous.len())
+                .filter(|&other| other != before)
+                .map(|other| (other, previous.pos[other].distance(previous.pos[before])))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+        });
+        Err(SimulationError::NonFinite {
+            step,
+            time,
+            body: bodies[body].label(),
+            closest: closest.map(|(other, distance)| (previous.body(other).label(), distance)),
+        })
+    }
+}
+
+/// Cubic Hermite interpolation between `p0` and `p1` with derivatives `m0` and
+/// `m1`, where `h` is the length of the interval and `s` runs from 0 to 1.
+pub(crate) fn hermite(
+    p0: Vector3<Float>,
+    m0: Vector3<Float>,
+    p1: Vector3<Float>,
+    m1: Vector3<Float>,
+    h: Float,
+    s: Float,
+) -> Vector3<Float> {
+    let s2 = s * s;
+    let s3 = s2 * s;
+    p0 * (2.0 * s3 - 3.0 * s2 + 1.0)
+        + m0 * (h * (s3 - 2.0 * s2 + s))
+        + p1 * (-2.0 * s3 + 3.0 * s2)
+        + m1 * (h * (s3 - s2))
+}
+
+struct Derivative {
+    vel: Vec<Vector3<Float>>,
+    acc: Vec<Vector3<Float>>,
+}
+
+/// `bodies` moved along a weighted sum of derivatives over a step of length `h`.
+fn advanced(bodies: &[Body], stages: &[(Float, &Derivative)], h: Float) -> Vec<Body> {
+    let mut bodies = bodies.to_vec();
+    for &(weight, k) in stages {
+        if weight == 0.0 {
+            continue;
+        }
+        for (i, body) in bodies.iter_mut().enumerate() {
+            body.pos += k.vel[i] * (weight * h);
+            body.vel += k.acc[i] * (weight * h);
+        }
+    }
+    bodies
+}
+
+/// Internal state of the adaptive integrator, which runs ahead of the sample grid.
+struct AdaptiveState {
+    time: Float,
+    bodies: Vec<Body>,
+    derivative: Derivative,
+    previous: Option<(Float, Vec<Body>, Derivative)>,
+    h: Float,
+}
+
+/// Advances a set of bodies through time with a fixed, possibly negative, `dt`.
+pub struct Stepper<'a> {
+    start_conditions: &'a StartConditions,
+    dt: Float,
+    time: Float,
+    accelerations: Option<Vec<Vector3<Float>>>,
+    particle_accelerations: Option<Vec<Vector3<Float>>>,
+    adaptive: Option<AdaptiveState>,
+}
+
+impl<'a> Stepper<'a> {
+    pub fn new(start_conditions: &'a StartConditions, dt: Float) -> Stepper<'a> {
+        Stepper {
+            start_conditions,
+            dt,
+            time: 0.0,
+            accelerations: None,
+            particle_accelerations: None,
+            adaptive: None,
+        }
+    }
+
+    /// Drops state carried between steps, for when the bodies were changed
+    /// from outside, e.g. by a collision.
+    pub fn reset(&mut self) {
+        self.accelerations = None;
+        self.particle_accelerations = None;
+        self.adaptive = None;
+    }
+
+    /// `f` of every index below `count`, spread over threads if requested.
+    fn per_body<T: Send>(&self, count: usize, f: impl Fn(usize) -> T + Sync + Send) -> Vec<T> {
+        if self.start_conditions.parallel {
+            (0..count).into_par_iter().map(f).collect()
+        } else {
+            (0..count).map(f).collect()
+        }
+    }
+
+    /// Pull of the `attractors` on massless particles at `pos`, in chunks when
+    /// running in parallel.
+    fn particle_gravity(
+        &self,
+        attractors: &Attractors,
+        pos: &[Vector3<Float>],
+        softening2: &[Float],
+    ) -> Vec<Vector3<Float>> {
+        const CHUNK: usize = 1024;
+        let gravity_strength = self.start_conditions.gravity_strength;
+        let mut accelerations = vec![Vector3::zero(); pos.len()];
+        if self.start_conditions.parallel {
+            accelerations
+                .par_chunks_mut(CHUNK)
+                .zip(pos.par_chunks(CHUNK).zip(softening2.par_chunks(CHUNK)))
+                .for_each(|(accelerations, (pos, softening2))| {
+                    attractors.pull_on_particles(pos, softening2, accelerations, gravity_strength)
+                });
+        } else {
+            attractors.pull_on_particles(pos, softening2, &mut accelerations, gravity_strength);
+        }
+        accelerations
+    }
+
+    /// Direct summation over the massive bodies. Massless bodies don't pull on
+    /// anything, so they get a separate pass over flat arrays.
+    fn direct_accelerations(&self, bodies: &[Body]) -> Vec<Vector3<Float>> {
+        let StartConditions {
+            gravity_strength,
+            softening,
+            ..
+        } = *self.start_conditions;
+        let attractors = Attractors::new(bodies, softening);
+        let mut accelerations = vec![Vector3::zero(); bodies.len()];
+        let massive = self.per_body(attractors.len(), |a| {
+            attractors.acceleration(a, gravity_strength)
+        });
+        for (&index, acceleration) in attractors.index.iter().zip(massive) {
+            accelerations[index] = acceleration;
+        }
+
+        let massless: Vec<usize> = (0..bodies.len())
+            .filter(|&index| bodies[index].mass == 0.0)
+            .collect();
+        let pos: Vec<Vector3<Float>> = massless.iter().map(|&i| bodies[i].pos).collect();
+        let softening2: Vec<Float> = massless
+            .iter()
+            .map(|&i| {
+                let softening = bodies[i].softening.unwrap_or(softening);
+                softening * softening
+            })
+            .collect();
+        let pulls = self.particle_gravity(&attractors, &pos, &softening2);
+        for (index, acceleration) in massless.into_iter().zip(pulls) {
+            accelerations[index] = acceleration;
+        }
+        accelerations
+    }
+
+    /// Accelerations of the test particles, summed in the same order as those
+    /// of the same bodies in `accelerations`.
+    fn particle_accelerations(
+        &self,
+        attractors: &Attractors,
+        particles: &TestParticles,
+    ) -> Vec<Vector3<Float>> {
+        let StartConditions {
+            gravity_strength,
+            softening,
+            ..
+        } = *self.start_conditions;
+        let mut accelerations =
+            self.particle_gravity(attractors, &particles.pos, &particles.softening2);
+        for force in &self.start_conditions.external_forces {
+            for ((acceleration, &pos), &vel) in accelerations
+                .iter_mut()
+                .zip(&particles.pos)
+                .zip(&particles.vel)
+            {
+                *acceleration += force.acceleration_at(pos, vel, gravity_strength, softening);
+            }
+        }
+        accelerations
+    }
+
+    /// Each body's acceleration is summed in the same order whether or not the
+    /// bodies are spread over threads, so parallel runs are bit-identical.
+    fn accelerations(&self, bodies: &[Body]) -> Vec<Vector3<Float>> {
+        let StartConditions {
+            gravity_strength,
+            softening,
+            light_speed,
+            ..
+        } = *self.start_conditions;
+        let mut accelerations = match self.start_conditions.solver {
+            GravitySolver::Direct => self.direct_accelerations(bodies),
+            GravitySolver::BarnesHut { theta } => {
+                let tree = Octree::new(bodies);
+                self.per_body(bodies.len(), |a| {
+                    tree.acceleration(a, gravity_strength, theta, softening)
+                })
+            }
+        };
+        for force in &self.start_conditions.external_forces {
+            for (acceleration, body) in accelerations.iter_mut().zip(bodies) {
+                *acceleration += force.acceleration(body, gravity_strength, softening);
+            }
+        }
+        // Scripted bodies follow their own path, which the corrections below
+        // need as well.
+        for (acceleration, body) in accelerations.iter_mut().zip(bodies) {
+            if let Some(scripted) = body.motion.acceleration(body) {
+                *acceleration = scripted;
+            }
+        }
+
+        if self.start_conditions.post_newtonian {
+            let potentials = self.per_body(bodies.len(), |a| {
+                potential(bodies, a, gravity_strength, softening)
+            });
+            let corrections = self.per_body(bodies.len(), |a| {
+                post_newtonian_correction(
+                    bodies,
+                    &accelerations,
+                    &potentials,
+                    a,
+                    gravity_strength,
+                    light_speed,
+                    softening,
+                )
+            });
+            for ((acceleration, correction), body) in
+                accelerations.iter_mut().zip(corrections).zip(bodies)
+            {
+                if body.motion.is_dynamic() {
+                    *acceleration += correction;
+                }
+            }
+        }
+        accelerations
+    }
+
+    fn derivative(&self, bodies: &[Body]) -> Derivative {
+        Derivative {
+            vel: bodies.iter().map(|body| body.vel).collect(),
+            acc: self.accelerations(bodies),
+        }
+    }
+
+    fn runge_kutta_4(&self, bodies: &mut [Body]) {
+        let dt = self.dt;
+        let k1 = self.derivative(bodies);
+        let k2 = self.derivative(&advanced(bodies, &[(0.5, &k1)], dt));
+        let k3 = self.derivative(&advanced(bodies, &[(0.5, &k2)], dt));
+        let k4 = self.derivative(&advanced(bodies, &[(1.0, &k3)], dt));
+        let result = advanced(
+            bodies,
+            &[
+                (1.0 / 6.0, &k1),
+                (1.0 / 3.0, &k2),
+                (1.0 / 3.0, &k3),
+                (1.0 / 6.0, &k4),
+            ],
+            dt,
+        );
+        bodies.clone_from_slice(&result);
+    }
+
+    /// One attempted Dormand-Prince step of length `h` from `state`. Returns the
+    /// new bodies, their derivative and the error estimate scaled by `tolerance`.
+    fn dormand_prince_step(
+        &self,
+        state: &AdaptiveState,
+        h: Float,
+        tolerance: Float,
+    ) -> (Vec<Body>, Derivative, Float) {
+        let y = &state.bodies;
+        let k1 = &state.derivative;
+        let k2 = self.derivative(&advanced(y, &[(1.0 / 5.0, k1)], h));
+        let k3 = self.derivative(&advanced(y, &[(3.0 / 40.0, k1), (9.0 / 40.0, &k2)], h));
+        let k4 = self.derivative(&advanced(
+            y,
+            &[(44.0 / 45.0, k1), (-56.0 / 15.0, &k2), (32.0 / 9.0, &k3)],
+            h,
+        ));
+        let k5 = self.derivative(&advanced(
+            y,
+            &[
+                (19372.0 / 6561.0, k1),
+                (-25360.0 / 2187.0, &k2),
+                (64448.0 / 6561.0, &k3),
+                (-212.0 / 729.0, &k4),
+            ],
+            h,
+        ));
+        let k6 = self.derivative(&advanced(
+            y,
+            &[
+                (9017.0 / 3168.0, k1),
+                (-355.0 / 33.0, &k2),
+                (46732.0 / 5247.0, &k3),
+                (49.0 / 176.0, &k4),
+                (-5103.0 / 18656.0, &k5),
+            ],
+            h,
+        ));
+        let next = advanced(
+            y,
+            &[
+                (35.0 / 384.0, k1),
+                (500.0 / 1113.0, &k3),
+                (125.0 / 192.0, &k4),
+                (-2187.0 / 6784.0, &k5),
+                (11.0 / 84.0, &k6),
+            ],
+            h,
+        );
+        let k7 = self.derivative(&next);
+
+        // Difference between the fifth- and embedded fourth-order solutions.
+        let error_weights = [
+            (71.0 / 57600.0, k1),
+            (-71.0 / 16695.0, &k3),
+            (71.0 / 1920.0, &k4),
+            (-17253.0 / 339200.0, &k5),
+            (22.0 / 525.0, &k6),
+            (-1.0 / 40.0, &k7),
+        ];
+        let mut error_norm: Float = 0.0;
+        for (i, (before, after)) in y.iter().zip(&next).enumerate() {
+            let mut pos_error = Vector3::zero();
+            let mut vel_error = Vector3::zero();
+            for &(weight, k) in &error_weights {
+                pos_error += k.vel[i] * (weight * h);
+                vel_error += k.acc[i] * (weight * h);
+            }
+            let pos_scale = tolerance * (1.0 + before.pos.magnitude().max(after.pos.magnitude()));
+            let vel_scale = tolerance * (1.0 + before.vel.magnitude().max(after.vel.magnitude()));
+            error_norm = error_norm
+                .max(pos_error.magnitude() / pos_scale)
+                .max(vel_error.magnitude() / vel_scale);
+        }
+        (next, k7, error_norm)
+    }
+
+    fn dormand_prince(&mut self, bodies: &mut [Body], tolerance: Float) {
+        let mut state = match self.adaptive.take() {
+            Some(state) if state.bodies.len() == bodies.len() => state,
+            _ => AdaptiveState {
+                time: self.time,
+                bodies: bodies.to_vec(),
+                derivative: self.derivative(bodies),
+                previous: None,
+                h: self.dt,
+            },
+        };
+        let target = self.time + self.dt;
+        let direction = self.dt.signum();
+        let min_step = self.dt.abs() * 1e-6;
+
+        while (target - state.time) * direction > 0.0 {
+            let mut h = state.h;
+            loop {
+                let (next, derivative, error) = self.dormand_prince_step(&state, h, tolerance);
+                let factor = if error == 0.0 {
+                    5.0
+                } else {
+                    (0.9 * error.powf(-0.2)).clamp(0.2, 5.0)
+                };
+                if error <= 1.0 || h.abs() <= min_step || !error.is_finite() {
+                    let mut next = next;
+                    constrain(&mut next, &state.bodies, state.time, h);
+                    let previous_bodies = std::mem::replace(&mut state.bodies, next);
+                    let previous_derivative = std::mem::replace(&mut state.derivative, derivative);
+                    state.previous = Some((state.time, previous_bodies, previous_derivative));
+                    state.time += h;
+                    state.h = if error.is_finite() { h * factor } else { h };
+                    break;
+                }
+                h = (h * factor).abs().max(min_step) * direction;
+            }
+        }
+
+        // Resample the internal steps that bracket `target` onto the grid.
+        match &state.previous {
+            Some((previous_time, previous_bodies, previous_derivative)) if state.time != target => {
+                let h = state.time - previous_time;
+                let s = (target - previous_time) / h;
+                for (i, body) in bodies.iter_mut().enumerate() {
+                    body.pos = hermite(
+                        previous_bodies[i].pos,
+                        previous_derivative.vel[i],
+                        state.bodies[i].pos,
+                        state.derivative.vel[i],
+                        h,
+                        s,
+                    );
+                    body.vel = hermite(
+                        previous_bodies[i].vel,
+                        previous_derivative.acc[i],
+                        state.bodies[i].vel,
+                        state.derivative.acc[i],
+                        h,
+                        s,
+                    );
+                }
+            }
+            _ => bodies.clone_from_slice(&state.bodies),
+        }
+        self.adaptive = Some(state);
+    }
+
+    /// Whether test particles are advanced apart from the other bodies. Only
+    /// the fixed-step integrators qualify, as they need the attractors at the
+    /// start and end of the step alone, and only with direct summation without
+    /// post-Newtonian terms, which is when a particle's acceleration depends on
+    /// nothing but the attractors.
+    fn separates_particles(&self) -> bool {
+        let start_conditions = self.start_conditions;
+        matches!(
+            start_conditions.integrator,
+            Integrator::Euler | Integrator::Leapfrog | Integrator::VelocityVerlet
+        ) && start_conditions.solver == GravitySolver::Direct
+            && !start_conditions.post_newtonian
+    }
+
+    /// Advances `bodies` by `dt`. Massless test particles are taken out into
+    /// flat arrays when the integrator allows it, so that the other bodies are
+    /// integrated on their own and the particles follow in one vectorized
+    /// pass. Either way every body ends up exactly where it would have.
+    pub fn step(&mut self, bodies: &mut [Body]) {
+        if !self.separates_particles() {
+            return self.step_bodies(bodies);
+        }
+        let softening = self.start_conditions.softening;
+        let mut particles = TestParticles::new(bodies, softening);
+        if particles.index.is_empty() {
+            return self.step_bodies(bodies);
+        }
+        let mut others: Vec<Body> = bodies
+            .iter()
+            .filter(|body| !TestParticles::contains(body))
+            .cloned()
+            .collect();
+        let before = Attractors::new(&others, softening);
+        self.step_bodies(&mut others);
+        let after = Attractors::new(&others, softening);
+        self.step_particles(&mut particles, &before, &after);
+
+        let remaining = bodies
+            .iter_mut()
+            .filter(|body| !TestParticles::contains(body));
+        for (body, other) in remaining.zip(others) {
+            *body = other;
+        }
+        particles.store(bodies);
+    }
+
+    /// Advances the test particles by `dt` while the attractors move from
+    /// `before` to `after`, mirroring the fixed-step branches of `step_bodies`.
+    fn step_particles(
+        &mut self,
+        particles: &mut TestParticles,
+        before: &Attractors,
+        after: &Attractors,
+    ) {
+        let dt = self.dt;
+        match self.start_conditions.integrator {
+            Integrator::Euler => {
+                let accelerations = self.particle_accelerations(before, particles);
+                let TestParticles { pos, vel, .. } = &mut *particles;
+                for ((pos, vel), acceleration) in pos.iter_mut().zip(vel).zip(accelerations) {
+                    *vel += acceleration * dt;
+                    *pos += *vel * dt;
+                }
+            }
+            Integrator::Leapfrog => {
+                let accelerations = match self.particle_accelerations.take() {
+                    Some(accelerations) => accelerations,
+                    None => self.particle_accelerations(before, particles),
+                };
+                let TestParticles { pos, vel, .. } = &mut *particles;
+                for ((pos, vel), acceleration) in pos.iter_mut().zip(vel).zip(accelerations) {
+                    *vel += acceleration * (dt * 0.5);
+                    *pos += *vel * dt;
+                }
+                let accelerations = self.particle_accelerations(after, particles);
+                for (vel, acceleration) in particles.vel.iter_mut().zip(&accelerations) {
+                    *vel += acceleration * (dt * 0.5);
+                }
+                self.particle_accelerations = Some(accelerations);
+            }
+            Integrator::VelocityVerlet => {
+                let old_accelerations = match self.particle_accelerations.take() {
+                    Some(accelerations) => accelerations,
+                    None => self.particle_accelerations(before, particles),
+                };
+                let TestParticles { pos, vel, .. } = &mut *particles;
+                for ((pos, vel), acceleration) in pos.iter_mut().zip(&*vel).zip(&old_accelerations)
+                {
+                    *pos += vel * dt + acceleration * (0.5 * dt * dt);
+                }
+                let accelerations = self.particle_accelerations(after, particles);
+                for ((vel, old), new) in particles
+                    .vel
+                    .iter_mut()
+                    .zip(&old_accelerations)
+                    .zip(&accelerations)
+                {
+                    *vel += (old + new) * (0.5 * dt);
+                }
+                self.particle_accelerations = Some(accelerations);
+            }
+            Integrator::RungeKutta4 | Integrator::DormandPrince { .. } => {
+                unreachable!("test particles are only separated for fixed-step integrators")
+            }
+        }
+    }
+
+    fn step_bodies(&mut self, bodies: &mut [Body]) {
+        let dt = self.dt;
+        let before = bodies
+            .iter()
+            .any(|body| !body.motion.is_dynamic())
+            .then(|| bodies.to_vec());
+        match self.start_conditions.integrator {
+            Integrator::Euler => {
+                let accelerations = self.accelerations(bodies);
+                for (body, acceleration) in bodies.iter_mut().zip(accelerations) {
+                    body.vel += acceleration * dt;
+                    body.pos += body.vel * dt;
+                }
+            }
+            Integrator::Leapfrog => {
+                let accelerations = match self.accelerations.take() {
+                    Some(accelerations) => accelerations,
+                    None => self.accelerations(bodies),
+                };
+                for (body, acceleration) in bodies.iter_mut().zip(accelerations) {
+                    body.vel += acceleration * (dt * 0.5);
+                    body.pos += body.vel * dt;
+                }
+                let accelerations = self.accelerations(bodies);
+                for (body, acceleration) in bodies.iter_mut().zip(&accelerations) {
+                    body.vel += acceleration * (dt * 0.5);
+                }
+                self.accelerations = Some(accelerations);
+            }
+            Integrator::VelocityVerlet => {
+                let old_accelerations = match self.accelerations.take() {
+                    Some(accelerations) => accelerations,
+                    None => self.accelerations(bodies),
+                };
+                for (body, acceleration) in bodies.iter_mut().zip(&old_accelerations) {
+                    body.pos += body.vel * dt + acceleration * (0.5 * dt * dt);
+                }
+                let accelerations = self.accelerations(bodies);
+                for ((body, old), new) in bodies
+                    .iter_mut()
+                    .zip(&old_accelerations)
+                    .zip(&accelerations)
+                {
+                    body.vel += (old + new) * (0.5 * dt);
+                }
+                self.accelerations = Some(accelerations);
+            }
+            Integrator::RungeKutta4 => self.runge_kutta_4(bodies),
+            Integrator::DormandPrince { tolerance } => self.dormand_prince(bodies, tolerance),
+        }
+        if let Some(before) = before {
+            constrain(bodies, &before, self.time, dt);
+        }
+        self.time += dt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+    use cgmath::vec3;
+
+    fn circular_orbit() -> Vec<Body> {
+        vec![
+            body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+            body(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 0.0),
+        ]
+    }
+
+    #[test]
+    fn symmetric_pair_stays_symmetric() {
+        for integrator in [
+            Integrator::Euler,
+            Integrator::Leapfrog,
+            Integrator::VelocityVerlet,
+        ] {
+            let mut bodies = vec![
+                body(vec3(-1.0, 0.0, 0.0), vec3(0.0, -0.5, 0.0), 1.0),
+                body(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.5, 0.0), 1.0),
+            ];
+            let start_conditions = start_conditions(bodies.clone(), integrator);
+            let mut stepper = Stepper::new(&start_conditions, start_conditions.dt);
+            for _ in 0..1000 {
+                stepper.step(&mut bodies);
+            }
+            assert_eq!(bodies[0].pos, -bodies[1].pos, "{integrator:?}");
+        }
+    }
+
+    #[test]
+    fn integrators_keep_circular_orbit() {
+        for integrator in [
+            Integrator::Leapfrog,
+            Integrator::VelocityVerlet,
+            Integrator::RungeKutta4,
+            Integrator::DormandPrince { tolerance: 1e-6 },
+        ] {
+            let mut bodies = circular_orbit();
+            let start_conditions = start_conditions(bodies.clone(), integrator);
+            let mut stepper = Stepper::new(&start_conditions, start_conditions.dt);
+            // Roughly ten orbits.
+            for _ in 0..6283 {
+                stepper.step(&mut bodies);
+                let radius = bodies[1].pos.magnitude();
+                assert!((radius - 1.0).abs() < 1e-3, "{integrator:?}: {radius}");
+            }
+        }
+    }
+
+    #[test]
+    fn dormand_prince_resolves_close_approach() {
+        // Highly eccentric orbit whose periapsis is far smaller than what a
+        // fixed step of this size can follow.
+        let make_bodies = || {
+            vec![
+                body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+                body(vec3(1.0, 0.0, 0.0), vec3(0.0, 0.2, 0.0), 0.0),
+            ]
+        };
+        let mut bodies = make_bodies();
+        let adaptive = start_conditions(
+            bodies.clone(),
+            Integrator::DormandPrince { tolerance: 1e-6 },
+        );
+        let mut stepper = Stepper::new(&adaptive, 0.05);
+        let energy = |body: &Body| 0.5 * body.vel.magnitude2() - 1.0 / body.pos.magnitude();
+        let initial_energy = energy(&bodies[1]);
+        // The orbital period is 2 * pi * a^1.5 with a = 1 / (2 - 0.04).
+        let period = std::f64::consts::TAU as Float * (1.0 / 1.96 as Float).powf(1.5);
+        for _ in 0..(period / 0.05) as usize {
+            stepper.step(&mut bodies);
+        }
+        assert!((energy(&bodies[1]) - initial_energy).abs() < 1e-3 * initial_energy.abs());
+
+        let mut bodies = make_bodies();
+        let euler = start_conditions(bodies.clone(), Integrator::Euler);
+        let mut stepper = Stepper::new(&euler, 0.05);
+        for _ in 0..(period / 0.05) as usize {
+            stepper.step(&mut bodies);
+        }
+        assert!((energy(&bodies[1]) - initial_energy).abs() > 1e-1 * initial_energy.abs());
+    }
+
+    #[test]
+    fn dormand_prince_samples_fixed_grid() {
+        let mut bodies = circular_orbit();
+        let start_conditions = start_conditions(
+            bodies.clone(),
+            Integrator::DormandPrince { tolerance: 1e-6 },
+        );
+        let dt = 0.001;
+        let mut stepper = Stepper::new(&start_conditions, dt);
+        for i in 1..=1000 {
+            stepper.step(&mut bodies);
+            let time = i as Float * dt;
+            let expected = vec3(time.cos(), time.sin(), 0.0);
+            assert!((bodies[1].pos - expected).magnitude() < 1e-4, "step {i}");
+        }
+    }
+
+    #[test]
+    fn softening_keeps_coincident_bodies_finite() {
+        let mut bodies = vec![
+            body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+            body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+        ];
+        let mut start_conditions = start_conditions(bodies.clone(), Integrator::Leapfrog);
+        start_conditions.softening = 0.1;
+        let mut stepper = Stepper::new(&start_conditions, start_conditions.dt);
+        stepper.step(&mut bodies);
+        assert_eq!(find_non_finite(&bodies), None);
+    }
+
+    #[test]
+    fn non_finite_bodies_are_reported() {
+        let mut start_conditions = start_conditions(
+            vec![
+                body(vec3(5.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+                body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+                body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+            ],
+            Integrator::Euler,
+        );
+        start_conditions.bodies[2].name = Some("core".to_string());
+        let error = crate::Universe::try_new(&start_conditions).unwrap_err();
+        assert_eq!(
+            error,
+            SimulationError::NonFinite {
+                step: 0,
+                time: -0.01,
+                body: "id 1".to_string(),
+                closest: Some(("\"core\" (id 2)".to_string(), 0.0)),
+            }
+        );
+
+        start_conditions.bodies[2].softening = Some(0.1);
+        assert!(crate::Universe::try_new(&start_conditions).is_ok());
+    }
+
+    #[test]
+    fn parallel_forces_are_bit_identical() {
+        let bodies: Vec<Body> = (0..150)
+            .map(|i| {
+                let angle = i as Float * 2.399;
+                let radius = 1.0 + (i % 17) as Float * 0.3;
+                body(
+                    vec3(
+                        angle.cos() * radius,
+                        angle.sin() * radius,
+                        (i % 5) as Float * 0.1,
+                    ),
+                    vec3(-angle.sin(), angle.cos(), 0.0) * 0.3,
+                    0.01 + (i % 3) as Float * 0.02,
+                )
+            })
+            .collect();
+        for solver in [
+            GravitySolver::Direct,
+            GravitySolver::BarnesHut { theta: 0.5 },
+        ] {
+            let mut serial = start_conditions(bodies.clone(), Integrator::Leapfrog);
+            serial.solver = solver;
+            serial.softening = 0.05;
+            serial.animation_length = 0.2;
+            let mut parallel = start_conditions(bodies.clone(), Integrator::Leapfrog);
+            parallel.solver = solver;
+            parallel.softening = 0.05;
+            parallel.animation_length = 0.2;
+            parallel.parallel = true;
+
+            let serial = crate::Universe::new(&serial);
+            let parallel = crate::Universe::new(&parallel);
+            assert_eq!(serial.bodies_path.len(), parallel.bodies_path.len());
+            for (a, b) in serial.bodies_path.steps().zip(parallel.bodies_path.steps()) {
+                assert_eq!(a.pos, b.pos, "{solver:?}");
+                assert_eq!(a.vel, b.vel, "{solver:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn post_newtonian_orbit_precesses() {
+        // Relative orbit with semi-major axis 1 and eccentricity 0.5 around a
+        // total mass of 1, starting at periapsis with the centre of mass at rest.
+        let (m1, m2) = (0.8, 0.2);
+        let (periapsis, speed): (Float, Float) = (0.5, (3.0 as Float).sqrt());
+        let bodies = vec![
+            body(
+                vec3(-m2 * periapsis, 0.0, 0.0),
+                vec3(0.0, -m2 * speed, 0.0),
+                m1,
+            ),
+            body(
+                vec3(m1 * periapsis, 0.0, 0.0),
+                vec3(0.0, m1 * speed, 0.0),
+                m2,
+            ),
+        ];
+        let light_speed = 20.0;
+
+        // Mean turn per orbit of the Runge-Lenz vector, sampled at each periapsis.
+        let precession = |post_newtonian| {
+            let mut bodies = bodies.clone();
+            let mut start_conditions = start_conditions(bodies.clone(), Integrator::RungeKutta4);
+            start_conditions.light_speed = light_speed;
+            start_conditions.post_newtonian = post_newtonian;
+            let mut stepper = Stepper::new(&start_conditions, 0.002);
+            let mut distances = vec![];
+            let mut angles: Vec<Float> = vec![];
+            let mut previous = None;
+            for _ in 0..(5.5 * std::f64::consts::TAU / 0.002) as usize {
+                let pos = bodies[1].pos - bodies[0].pos;
+                let vel = bodies[1].vel - bodies[0].vel;
+                distances.push(pos.magnitude());
+                if let [.., before, at, after] = distances[..] {
+                    if at < before && at <= after {
+                        let runge_lenz: Vector3<Float> = previous.unwrap();
+                        angles.push(runge_lenz.y.atan2(runge_lenz.x));
+                    }
+                }
+                previous = Some(vel.cross(pos.cross(vel)) - pos.normalize());
+                stepper.step(&mut bodies);
+            }
+            assert!(angles.len() >= 4, "{angles:?}");
+            (angles[angles.len() - 1] - angles[0]) / (angles.len() - 1) as Float
+        };
+
+        // 6 pi G M / (c^2 a (1 - e^2)) per orbit.
+        let expected = 6.0 * std::f64::consts::PI as Float / (light_speed * light_speed * 0.75);
+        let relativistic = precession(true);
+        assert!(
+            (relativistic - expected).abs() < 0.05 * expected,
+            "{relativistic} vs {expected}"
+        );
+        assert!(precession(false).abs() < 1e-3 * expected);
+    }
+
+    #[test]
+    fn test_particles_match_direct_sum_and_leave_others_alone() {
+        let massive = vec![
+            body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+            body(vec3(3.0, 0.0, 0.0), vec3(0.0, 0.6, 0.0), 0.1),
+            body(vec3(0.0, -5.0, 0.5), vec3(0.4, 0.0, 0.0), 0.05),
+        ];
+        let mut bodies = massive.clone();
+        for i in 0..2500 {
+            let angle = i as Float * 0.01;
+            let radius = 1.5 + (i % 50) as Float * 0.05;
+            let mut particle = body(
+                vec3(angle.cos(), angle.sin(), 0.0) * radius,
+                vec3(-angle.sin(), angle.cos(), 0.0) / radius.sqrt(),
+                0.0,
+            );
+            if i % 3 == 0 {
+                particle.softening = Some(0.2);
+            }
+            bodies.push(particle);
+        }
+
+        let mut start_conditions = start_conditions(bodies.clone(), Integrator::Leapfrog);
+        start_conditions.softening = 0.05;
+        for parallel in [false, true] {
+            start_conditions.parallel = parallel;
+            let stepper = Stepper::new(&start_conditions, start_conditions.dt);
+            assert_eq!(
+                stepper.accelerations(&bodies),
+                accelerations(&bodies, 1.0, 0.05)
+            );
+        }
+
+        let mut alone = start_conditions.clone();
+        alone.bodies = massive;
+        let with_particles = crate::Universe::new(&start_conditions);
+        let alone = crate::Universe::new(&alone);
+        for (a, b) in with_particles
+            .bodies_path
+            .steps()
+            .zip(alone.bodies_path.steps())
+        {
+            assert_eq!(a.pos[..3], b.pos[..]);
+        }
+    }
+
+    #[test]
+    fn test_particles_move_as_if_integrated_with_the_rest() {
+        let mut bodies = vec![
+            body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+            body(vec3(3.0, 0.0, 0.0), vec3(0.0, 0.6, 0.0), 0.1),
+        ];
+        let mut fixed = body(vec3(0.0, 2.0, 0.0), vec3(0.0, 0.0, 0.0), 0.0);
+        fixed.motion = crate::Motion::Fixed;
+        bodies.push(fixed);
+        for i in 0..3000 {
+            let angle = i as Float * 0.01;
+            let radius = 1.5 + (i % 50) as Float * 0.05;
+            let mut particle = body(
+                vec3(angle.cos(), angle.sin(), 0.0) * radius,
+                vec3(-angle.sin(), angle.cos(), 0.0) / radius.sqrt(),
+                0.0,
+            );
+            if i % 3 == 0 {
+                particle.softening = Some(0.2);
+            }
+            bodies.push(particle);
+        }
+
+        for integrator in [
+            Integrator::Euler,
+            Integrator::Leapfrog,
+            Integrator::VelocityVerlet,
+        ] {
+            for parallel in [false, true] {
+                let mut start_conditions = start_conditions(bodies.clone(), integrator);
+                start_conditions.softening = 0.05;
+                start_conditions.parallel = parallel;
+                start_conditions.external_forces =
+                    vec![crate::ExternalForce::Drag { coefficient: 0.1 }];
+                let universe = crate::Universe::new(&start_conditions);
+
+                // Everything advanced together, without taking the particles out.
+                let mut together = start_conditions.initial_bodies().unwrap();
+                let mut stepper = Stepper::new(&start_conditions, start_conditions.dt);
+                for i in universe.light_iter_count()..universe.bodies_path.len() {
+                    let step = universe.bodies_path.step(i);
+                    let pos: Vec<_> = together.iter().map(|body| body.pos).collect();
+                    let vel: Vec<_> = together.iter().map(|body| body.vel).collect();
+                    assert!(
+                        step.pos == pos && step.vel == vel,
+                        "{integrator:?}, step {i}"
+                    );
+                    stepper.step_bodies(&mut together);
+                }
+            }
+        }
+    }
+}
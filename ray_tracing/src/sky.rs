@@ -0,0 +1,412 @@
+use crate::{snapshot::invalid_data, to_f32, Float};
+use cgmath::{InnerSpace, Vector3};
+use simple_video::ColorF32;
+use std::{
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Equirectangular image of the sky that escaped photons show, laid out the
+/// way the default camera sees the scene: -y at the top, +z in the middle and
+/// +x a quarter of the way to the right of it. A default camera with an
+/// `Equirectangular` projection shows the image as it is.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Sky {
+    /// PPM, PNG or Radiance HDR file, relative to the `.render` file.
+    pub image: PathBuf,
+    /// Multiplies the colors of the image, mostly to expose HDR images, which
+    /// are otherwise clipped at 1 like everything else.
+    #[serde(default = "Sky::default_brightness")]
+    pub brightness: f32,
+}
+
+impl Sky {
+    fn default_brightness() -> f32 {
+        1.0
+    }
+
+    /// Loads the image, with a relative path looked up in `dir`.
+    pub fn load(&self, dir: impl AsRef<Path>) -> std::io::Result<SkyMap> {
+        let mut map = SkyMap::decode(&std::fs::read(dir.as_ref().join(&self.image))?)?;
+        for pixel in &mut map.pixels {
+            pixel.r *= self.brightness;
+            pixel.g *= self.brightness;
+            pixel.b *= self.brightness;
+        }
+        Ok(map)
+    }
+}
+
+/// Decoded sky image, see `Sky`.
+#[derive(Clone, Debug)]
+pub struct SkyMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<ColorF32>,
+}
+
+impl SkyMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<ColorF32>) -> SkyMap {
+        assert!(width > 0 && height > 0);
+        assert_eq!(pixels.len(), width * height);
+        SkyMap {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Reads a PPM, PNG or Radiance HDR file, told apart by its first bytes.
+    pub fn decode(bytes: &[u8]) -> std::io::Result<SkyMap> {
+        if bytes.starts_with(b"P6") {
+            decode_ppm(bytes)
+        } else if bytes.starts_with(b"\x89PNG") {
+            decode_png(bytes)
+        } else if bytes.starts_with(b"#?") {
+            decode_hdr(bytes)
+        } else {
+            Err(invalid_data(
+                "sky image is not a binary PPM, PNG or HDR file",
+            ))
+        }
+    }
+
+    /// Bilinearly filtered color in `direction`, which doesn't have to be
+    /// normalized.
+    pub fn sample(&self, direction: Vector3<Float>) -> ColorF32 {
+        let direction = direction.normalize();
+        let longitude = direction.x.atan2(direction.z);
+        let latitude = direction.y.clamp(-1.0, 1.0).asin();
+        let x = (0.5 + longitude / std::f64::consts::TAU as Float) * self.width as Float - 0.5;
+        let y = (0.5 + latitude / std::f64::consts::PI as Float) * self.height as Float - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (s, t) = (to_f32(x - x0), to_f32(y - y0));
+
+        // Wraps around horizontally and stops at the poles.
+        let pixel = |x: Float, y: Float| {
+            let column = (x as i64).rem_euclid(self.width as i64) as usize;
+            let row = (y.max(0.0) as usize).min(self.height - 1);
+            self.pixels[row * self.width + column]
+        };
+        let blend = |a: ColorF32, b: ColorF32, t: f32| ColorF32 {
+            r: a.r + (b.r - a.r) * t,
+            g: a.g + (b.g - a.g) * t,
+            b: a.b + (b.b - a.b) * t,
+        };
+        blend(
+            blend(pixel(x0, y0), pixel(x0 + 1.0, y0), s),
+            blend(pixel(x0, y0 + 1.0), pixel(x0 + 1.0, y0 + 1.0), s),
+            t,
+        )
+    }
+}
+
+/// Binary PPM with 8 or 16 bits per channel.
+fn decode_ppm(bytes: &[u8]) -> std::io::Result<SkyMap> {
+    let mut at = 2;
+    let mut header = [0; 3];
+    for value in &mut header {
+        loop {
+            match bytes.get(at) {
+                Some(byte) if byte.is_ascii_whitespace() => at += 1,
+                Some(b'#') => {
+                    while bytes.get(at).is_some_and(|&byte| byte != b'\n') {
+                        at += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        let digits = bytes[at..].iter().take_while(|byte| byte.is_ascii_digit());
+        let length = digits.count();
+        *value = std::str::from_utf8(&bytes[at..at + length])
+            .unwrap()
+            .parse()
+            .map_err(|_| invalid_data("broken PPM header"))?;
+        at += length;
+    }
+    let [width, height, max] = header;
+    if width == 0 || height == 0 || max == 0 || max > u16::MAX as usize {
+        return Err(invalid_data("broken PPM header"));
+    }
+    // A single whitespace byte separates the header from the pixels.
+    let data = bytes.get(at + 1..).unwrap_or_default();
+    let channel_size = if max > u8::MAX as usize { 2 } else { 1 };
+    let size = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(3 * channel_size));
+    if size.is_none_or(|size| data.len() < size) {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    let channel = |i: usize| {
+        let value = match channel_size {
+            1 => data[i] as f32,
+            _ => u16::from_be_bytes([data[2 * i], data[2 * i + 1]]) as f32,
+        };
+        value / max as f32
+    };
+    let pixels = (0..width * height)
+        .map(|i| ColorF32 {
+            r: channel(3 * i),
+            g: channel(3 * i + 1),
+            b: channel(3 * i + 2),
+        })
+        .collect();
+    Ok(SkyMap::new(width, height, pixels))
+}
+
+fn decode_png(bytes: &[u8]) -> std::io::Result<SkyMap> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    let channels = info.color_type.samples();
+    let (width, height) = (info.width as usize, info.height as usize);
+    let pixels = data[..info.buffer_size()]
+        .chunks_exact(channels)
+        .map(|pixel| {
+            // Grey images have one channel, and alpha is ignored.
+            let [r, g, b] = match channels {
+                1 | 2 => [pixel[0]; 3],
+                _ => [pixel[0], pixel[1], pixel[2]],
+            };
+            ColorF32 {
+                r: r as f32 / 255.0,
+                g: g as f32 / 255.0,
+                b: b as f32 / 255.0,
+            }
+        })
+        .collect();
+    Ok(SkyMap::new(width, height, pixels))
+}
+
+/// Radiance RGBE image in the usual `-Y height +X width` orientation, flat or
+/// run-length encoded.
+fn decode_hdr(bytes: &[u8]) -> std::io::Result<SkyMap> {
+    let broken = || invalid_data("broken HDR header");
+    let mut lines = bytes.split(|&byte| byte == b'\n');
+    let mut at = 0;
+    let mut next_line = || {
+        let line = lines.next()?;
+        at += line.len() + 1;
+        std::str::from_utf8(line).ok()
+    };
+    loop {
+        let line = next_line().ok_or_else(broken)?;
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_data(format!("unsupported HDR {line}")));
+        }
+        if line.is_empty() {
+            break;
+        }
+    }
+    let resolution = next_line().ok_or_else(broken)?;
+    let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (
+            width.parse::<usize>().map_err(|_| broken())?,
+            height.parse::<usize>().map_err(|_| broken())?,
+        ),
+        _ => return Err(invalid_data(format!("unsupported HDR layout {resolution}"))),
+    };
+    if width == 0 || height == 0 || width.checked_mul(height).is_none() {
+        return Err(broken());
+    }
+
+    // The image grows one scanline at a time, so a header that promises more
+    // than the file holds runs out of bytes instead of memory.
+    let eof = || Error::from(ErrorKind::UnexpectedEof);
+    let mut data = bytes.get(at..).ok_or_else(eof)?.iter().copied();
+    let mut rgbe = vec![];
+    let mut scanline = vec![];
+    for _ in 0..height {
+        // Only scanlines narrower than 0x8000 can be run-length encoded, wider
+        // ones need four bytes per pixel.
+        if width >= 0x8000 && data.len() / 4 < width {
+            return Err(eof());
+        }
+        scanline.resize(width, [0; 4]);
+        let start: Vec<u8> = data.clone().take(4).collect();
+        let run_length_encoded = (8..0x8000).contains(&width)
+            && start.len() == 4
+            && start[..2] == [2, 2]
+            && (start[2] as usize) << 8 | start[3] as usize == width;
+        if !run_length_encoded {
+            for pixel in &mut scanline {
+                for channel in pixel {
+                    *channel = data.next().ok_or_else(eof)?;
+                }
+            }
+            rgbe.extend_from_slice(&scanline);
+            continue;
+        }
+        data.nth(3);
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = data.next().ok_or_else(eof)? as usize;
+                let (count, repeated) = match count {
+                    129.. => (count - 128, Some(data.next().ok_or_else(eof)?)),
+                    _ => (count, None),
+                };
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("broken HDR run"));
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = match repeated {
+                        Some(value) => value,
+                        None => data.next().ok_or_else(eof)?,
+                    };
+                }
+                x += count;
+            }
+        }
+        rgbe.extend_from_slice(&scanline);
+    }
+    let pixels = rgbe
+        .into_iter()
+        .map(|[r, g, b, e]| {
+            let scale = if e == 0 {
+                0.0
+            } else {
+                2.0f32.powi(e as i32 - 136)
+            };
+            ColorF32 {
+                r: r as f32 * scale,
+                g: g as f32 * scale,
+                b: b as f32 * scale,
+            }
+        })
+        .collect();
+    Ok(SkyMap::new(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::vec3;
+
+    fn close(a: ColorF32, b: ColorF32) -> bool {
+        (a.r - b.r).abs() < 1e-3 && (a.g - b.g).abs() < 1e-3 && (a.b - b.b).abs() < 1e-3
+    }
+
+    fn grey(value: f32) -> ColorF32 {
+        ColorF32 {
+            r: value,
+            g: value,
+            b: value,
+        }
+    }
+
+    #[test]
+    fn samples_between_pixel_centres() {
+        // Columns from the back, through -x, the front and +x.
+        let sky = SkyMap::new(
+            4,
+            2,
+            [0.0, 0.2, 0.4, 0.6, 0.1, 0.3, 0.5, 0.7].map(grey).to_vec(),
+        );
+        assert!(close(sky.sample(vec3(0.0, 0.0, 1.0)), grey(0.35)));
+        assert!(close(sky.sample(vec3(1.0, 0.0, 0.0)), grey(0.55)));
+        assert!(close(sky.sample(vec3(-1.0, 0.0, 0.0)), grey(0.15)));
+        assert!(close(sky.sample(vec3(0.0, 0.0, -3.0)), grey(0.35)));
+        assert!(close(sky.sample(vec3(0.0, -1.0, 0.0)), grey(0.3)));
+        assert!(close(sky.sample(vec3(0.0, 1.0, 0.0)), grey(0.4)));
+    }
+
+    #[test]
+    fn decodes_every_format() {
+        let mut ppm = b"P6\n# sky\n2 1\n255\n".to_vec();
+        ppm.extend([255, 0, 51, 0, 255, 0]);
+        let ppm = SkyMap::decode(&ppm).unwrap();
+        assert_eq!((ppm.width, ppm.height), (2, 1));
+        assert!(close(
+            ppm.pixels[0],
+            ColorF32 {
+                r: 1.0,
+                g: 0.0,
+                b: 0.2
+            }
+        ));
+
+        let mut png = vec![];
+        let mut encoder = png::Encoder::new(&mut png, 1, 2);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_image_data(&[0, 51, 255, 9, 255, 0, 0, 9])
+            .unwrap();
+        writer.finish().unwrap();
+        let png = SkyMap::decode(&png).unwrap();
+        assert_eq!((png.width, png.height), (1, 2));
+        assert!(close(
+            png.pixels[1],
+            ColorF32 {
+                r: 1.0,
+                g: 0.0,
+                b: 0.0
+            }
+        ));
+
+        // One flat scanline and one run-length encoded one of 8 pixels.
+        let mut hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        for _ in 0..8 {
+            hdr.extend([128, 64, 0, 129]);
+        }
+        hdr.extend([2, 2, 0, 8]);
+        hdr.extend([136, 128, 2, 0, 0, 134, 64, 136, 0, 136, 131]);
+        let hdr = SkyMap::decode(&hdr).unwrap();
+        assert_eq!((hdr.width, hdr.height), (8, 2));
+        assert!(close(
+            hdr.pixels[3],
+            ColorF32 {
+                r: 1.0,
+                g: 0.5,
+                b: 0.0
+            }
+        ));
+        assert!(close(
+            hdr.pixels[8],
+            ColorF32 {
+                r: 4.0,
+                g: 0.0,
+                b: 0.0
+            }
+        ));
+        assert!(close(
+            hdr.pixels[15],
+            ColorF32 {
+                r: 4.0,
+                g: 2.0,
+                b: 0.0
+            }
+        ));
+        assert!(close(
+            hdr.pixels[10],
+            ColorF32 {
+                r: 4.0,
+                g: 2.0,
+                b: 0.0
+            }
+        ));
+
+        assert!(SkyMap::decode(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn headers_larger_than_the_file_run_out_of_bytes() {
+        let kind = |bytes: &[u8]| SkyMap::decode(bytes).err().map(|error| error.kind());
+        let eof = Some(ErrorKind::UnexpectedEof);
+        assert_eq!(kind(b"#?RADIANCE\n\n-Y 200000 +X 200000\n"), eof);
+        let mut narrow = b"#?RADIANCE\n\n-Y 4000000000 +X 8\n".to_vec();
+        narrow.extend([128; 32]);
+        assert_eq!(kind(&narrow), eof);
+        assert_eq!(
+            kind(b"#?RADIANCE\n\n-Y 18446744073709551615 +X 2\n"),
+            Some(ErrorKind::InvalidData)
+        );
+
+        assert_eq!(kind(b"P6\n200000 200000\n255\n\0\0\0"), eof);
+        assert_eq!(kind(b"P6\n4294967296 4294967296\n65535\n\0\0\0"), eof);
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{BodyHistory, ExternalForce, Float, Interpolation, MergeEvent, Universe};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+const MAGIC_BYTES: [u8; 6] = *b"rtsnap";
+
+/// Bumped whenever the layout changes; older snapshots are rejected.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+pub(crate) fn invalid_data(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+pub(crate) fn write_u32(mut f: impl Write, value: u32) -> std::io::Result<()> {
+    f.write_all(&value.to_be_bytes())
+}
+
+pub(crate) fn read_u32(mut f: impl Read) -> std::io::Result<u32> {
+    let mut value = [0; size_of::<u32>()];
+    f.read_exact(&mut value)?;
+    Ok(u32::from_be_bytes(value))
+}
+
+pub(crate) fn write_u64(mut f: impl Write, value: u64) -> std::io::Result<()> {
+    f.write_all(&value.to_be_bytes())
+}
+
+pub(crate) fn read_u64(mut f: impl Read) -> std::io::Result<u64> {
+    let mut value = [0; size_of::<u64>()];
+    f.read_exact(&mut value)?;
+    Ok(u64::from_be_bytes(value))
+}
+
+pub(crate) fn write_float(mut f: impl Write, value: Float) -> std::io::Result<()> {
+    f.write_all(&value.to_be_bytes())
+}
+
+/// Reads a float stored with `size` bytes, so that snapshots can be shared
+/// between builds with and without the `f64` feature.
+pub(crate) fn read_float(mut f: impl Read, size: u8) -> std::io::Result<Float> {
+    Ok(match size {
+        4 => {
+            let mut value = [0; 4];
+            f.read_exact(&mut value)?;
+            f32::from_be_bytes(value) as Float
+        }
+        8 => {
+            let mut value = [0; 8];
+            f.read_exact(&mut value)?;
+            f64::from_be_bytes(value) as Float
+        }
+        _ => return Err(invalid_data(format!("unsupported float size {size}"))),
+    })
+}
+
+/// Length-prefixed JSON, for the small and irregular parts of a snapshot.
+pub(crate) fn write_json(mut f: impl Write, value: &impl serde::Serialize) -> std::io::Result<()> {
+    let json = serde_json::to_vec(value)?;
+    write_u64(&mut f, json.len() as u64)?;
+    f.write_all(&json)
+}
+
+pub(crate) fn read_json<T: serde::de::DeserializeOwned>(mut f: impl Read) -> std::io::Result<T> {
+    let length = read_u64(&mut f)?;
+    let mut json = vec![];
+    f.by_ref().take(length).read_to_end(&mut json)?;
+    if json.len() as u64 != length {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Everything in a `Universe` apart from its `bodies_path`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Settings {
+    time: Float,
+    animation_length: Float,
+    max_distance: Float,
+    light_speed: Float,
+    gravity_strength: Float,
+    dt: Float,
+    softening: Float,
+    merge_events: Vec<MergeEvent>,
+    interpolation: Interpolation,
+    external_forces: Vec<ExternalForce>,
+}
+
+/// Writes `universe` as a binary snapshot: the magic bytes, the version, the
+/// size of a float, the settings as JSON and then the body history, whose
+/// states are stored as flat big-endian arrays.
+pub fn write_universe(universe: &Universe, mut f: impl Write) -> std::io::Result<()> {
+    f.write_all(&MAGIC_BYTES)?;
+    write_u32(&mut f, SNAPSHOT_VERSION)?;
+    f.write_all(&[size_of::<Float>() as u8])?;
+    write_json(
+        &mut f,
+        &Settings {
+            time: universe.time,
+            animation_length: universe.animation_length,
+            max_distance: universe.max_distance,
+            light_speed: universe.light_speed,
+            gravity_strength: universe.gravity_strength,
+            dt: universe.dt,
+            softening: universe.softening,
+            merge_events: universe.merge_events.clone(),
+            interpolation: universe.interpolation,
+            external_forces: universe.external_forces.clone(),
+        },
+    )?;
+    universe.bodies_path.write(&mut f)?;
+    f.flush()
+}
+
+pub fn write_universe_to_file(universe: &Universe, path: impl AsRef<Path>) -> std::io::Result<()> {
+    write_universe(universe, BufWriter::new(File::create(path)?))
+}
+
+pub fn read_universe(mut f: impl Read) -> std::io::Result<Universe> {
+    let mut magic = [0; 6];
+    f.read_exact(&mut magic)?;
+    if magic != MAGIC_BYTES {
+        return Err(invalid_data("not a universe snapshot"));
+    }
+    let version = read_u32(&mut f)?;
+    if version != SNAPSHOT_VERSION {
+        return Err(invalid_data(format!(
+            "snapshot version {version} is not supported, expected {SNAPSHOT_VERSION}"
+        )));
+    }
+    let mut float_size = 0;
+    f.read_exact(std::slice::from_mut(&mut float_size))?;
+    let settings: Settings = read_json(&mut f)?;
+    let bodies_path = BodyHistory::read(&mut f, float_size)?;
+
+    Ok(Universe {
+        time: settings.time,
+        animation_length: settings.animation_length,
+        bodies_path,
+        max_distance: settings.max_distance,
+        light_speed: settings.light_speed,
+        gravity_strength: settings.gravity_strength,
+        dt: settings.dt,
+        softening: settings.softening,
+        merge_events: settings.merge_events,
+        interpolation: settings.interpolation,
+        external_forces: settings.external_forces,
+    })
+}
+
+pub fn read_universe_from_file(path: impl AsRef<Path>) -> std::io::Result<Universe> {
+    read_universe(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{body, start_conditions};
+    use crate::{CollisionMode, Integrator};
+    use cgmath::vec3;
+
+    #[test]
+    fn snapshot_round_trips() {
+        let mut start_conditions = start_conditions(
+            vec![
+                body(vec3(-1.0, 0.0, 0.0), vec3(2.0, 0.0, 0.0), 1.0),
+                body(vec3(1.0, 0.0, 0.0), vec3(-2.0, 0.0, 0.0), 1.0),
+                body(vec3(0.0, 5.0, 0.0), vec3(0.0, 0.0, 0.0), 0.0),
+            ],
+            Integrator::Leapfrog,
+        );
+        start_conditions.bodies[2].name = Some("tracer".to_string());
+        start_conditions.softening = 0.05;
+        start_conditions.collisions = CollisionMode::Merge;
+        start_conditions.external_forces = vec![ExternalForce::Drag { coefficient: 0.1 }];
+        let universe = Universe::new(&start_conditions);
+
+        let mut bytes = vec![];
+        write_universe(&universe, &mut bytes).unwrap();
+        let read = read_universe(&bytes[..]).unwrap();
+
+        assert_eq!(read.bodies_path.len(), universe.bodies_path.len());
+        for (a, b) in read.bodies_path.steps().zip(universe.bodies_path.steps()) {
+            assert_eq!((a.ids, a.pos, a.vel, a.mass), (b.ids, b.pos, b.vel, b.mass));
+        }
+        assert_eq!(read.find_body("tracer"), Some(2));
+        assert_eq!(read.merge_events, universe.merge_events);
+        assert_eq!(read.external_forces, universe.external_forces);
+        assert_eq!(read.dt, universe.dt);
+
+        let mut newer = bytes.clone();
+        newer[9] += 1;
+        assert_eq!(
+            read_universe(&newer[..]).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(read_universe(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use crate::{
+    Body, Camera, CollisionMode, Float, GravitySolver, Integrator, Interpolation, Motion,
+    StartConditions,
+};
+use cgmath::Vector3;
+use simple_video::ColorF32;
+
+pub fn body(pos: Vector3<Float>, vel: Vector3<Float>, mass: Float) -> Body {
+    Body {
+        id: Body::UNASSIGNED_ID,
+        name: None,
+        pos,
+        vel,
+        radius: 0.1,
+        color: ColorF32 {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+        },
+        mass,
+        softening: None,
+        motion: Motion::Dynamic,
+        orbit: None,
+    }
+}
+
+pub fn start_conditions(bodies: Vec<Body>, integrator: Integrator) -> StartConditions {
+    StartConditions {
+        width: 1,
+        height: 1,
+        fps: 1,
+        time: 0.0,
+        animation_length: 1.0,
+        bodies,
+        generators: vec![],
+        max_distance: 1.0,
+        light_speed: 1.0,
+        gravity_strength: 1.0,
+        dt: 0.01,
+        integrator,
+        solver: GravitySolver::Direct,
+        softening: 0.0,
+        post_newtonian: false,
+        external_forces: vec![],
+        collisions: CollisionMode::Ignore,
+        interpolation: Interpolation::Hermite,
+        parallel: false,
+        camera: Camera::default(),
+        sky: None,
+    }
+}